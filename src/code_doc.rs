@@ -1,12 +1,47 @@
+use super::interpreter::*;
+use super::params::*;
+use super::query::*;
+use super::timestamp::*;
+
 pub struct CodeBlock {
     pub interpreter: String,
     pub code: String,
+    pub header_args: Vec<(String, String)>,
+    ///line of the #+begin_src in the org file
+    pub linum: usize,
+    ///from a `#+NAME:` line right before the block
+    pub name: Option<String>,
+}
+
+impl CodeBlock {
+    ///value of the first `:key value` header argument; keys are given without the colon
+    pub fn header_arg(&self, key: &str) -> Option<&str> {
+        self.header_args
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
+    }
+
+    ///the `:stdin` header argument, or `:input`, which means the same
+    pub fn stdin(&self) -> Option<&str> {
+        return self
+            .header_arg("stdin")
+            .or_else(|| self.header_arg("input"));
+    }
 }
 
 pub struct RunnableCode {
     pub interpreter: String,
     pub fullname: Vec<String>,
     pub code: Vec<String>,
+    ///files the job produces, from CREATES properties and :creates header args
+    pub creates: Vec<String>,
+    ///files the job reads, from SOURCES properties and :sources header args
+    pub sources: Vec<String>,
+    ///declared parameters, from ARGS properties and :var header args
+    pub params: Vec<Param>,
+    ///heading and line of every code block, in the order they run
+    pub origin: Vec<(DocNodeId, usize)>,
 }
 
 pub type DocNodeId = usize;
@@ -15,20 +50,73 @@ pub struct DocNode {
     name: String,
     level: i32,
     code_blocks: Vec<CodeBlock>,
+    properties: Vec<(String, String)>,
+    tags: Vec<String>,
+    ///line of the heading in the org file, 0 when it has none
+    linum: usize,
+    ///from a `SCHEDULED: <...>` planning line
+    scheduled: Option<Timestamp>,
 }
 
 impl DocNode {
     pub fn name(&self) -> &str {
         &self.name
     }
+    pub fn code_blocks(&self) -> &[CodeBlock] {
+        &self.code_blocks
+    }
+    #[allow(dead_code)]
     pub fn level(&self) -> i32 {
         self.level
     }
+    ///value of a property from the heading's property drawer; keys are case-insensitive
+    pub fn property(&self, key: &str) -> Option<&str> {
+        self.properties
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
+    }
+    ///whether the heading itself is tagged, e.g. `* deploy :danger:`
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+    pub fn linum(&self) -> usize {
+        self.linum
+    }
+    pub fn scheduled(&self) -> Option<&Timestamp> {
+        self.scheduled.as_ref()
+    }
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub enum DependencyError {
+    Unresolved {
+        node: DocNodeId,
+        query: String,
+    },
+    Ambiguous {
+        node: DocNodeId,
+        query: String,
+        matches: Vec<DocNodeId>,
+    },
+    Cycle {
+        nodes: Vec<DocNodeId>,
+    },
+    InvalidQuery {
+        node: DocNodeId,
+        error: QueryError,
+    },
 }
 
 pub struct CodeDoc {
     nodes: Vec<DocNode>,
     parent: Vec<DocNodeId>,
+    ///file-level `#+KEY: value` lines, in order
+    keywords: Vec<(String, String)>,
+    ///text of the example blocks with a `#+NAME:`
+    examples: Vec<(String, String)>,
+    ///line number and text of SCHEDULED lines orgjob cannot schedule
+    unscheduled: Vec<(usize, String)>,
 }
 
 pub const DOC_NODE_ROOT_ID: DocNodeId = 0;
@@ -38,11 +126,18 @@ impl CodeDoc {
         let mut doc = CodeDoc {
             nodes: Vec::new(),
             parent: Vec::new(),
+            keywords: Vec::new(),
+            examples: Vec::new(),
+            unscheduled: Vec::new(),
         };
         doc.nodes.push(DocNode {
             level: -1,
             name: String::new(),
             code_blocks: Vec::new(),
+            properties: Vec::new(),
+            tags: Vec::new(),
+            linum: 0,
+            scheduled: None,
         });
         doc.parent.push(DOC_NODE_ROOT_ID);
         return doc;
@@ -61,11 +156,78 @@ impl CodeDoc {
             name,
             level,
             code_blocks,
+            properties: Vec::new(),
+            tags: Vec::new(),
+            linum: 0,
+            scheduled: None,
         });
         self.parent.push(parent);
         return id;
     }
 
+    pub fn set_property(&mut self, node: DocNodeId, key: String, value: String) {
+        assert!(node < self.nodes.len());
+        assert!(node != DOC_NODE_ROOT_ID);
+        let props = &mut self.nodes[node].properties;
+        match props.iter_mut().find(|(k, _)| k.eq_ignore_ascii_case(&key)) {
+            Some(prop) => prop.1 = value,
+            None => props.push((key, value)),
+        }
+    }
+
+    pub fn set_linum(&mut self, node: DocNodeId, linum: usize) {
+        assert!(node < self.nodes.len());
+        self.nodes[node].linum = linum;
+    }
+
+    pub fn set_scheduled(&mut self, node: DocNodeId, scheduled: Option<Timestamp>) {
+        assert!(node < self.nodes.len());
+        self.nodes[node].scheduled = scheduled;
+    }
+
+    pub fn set_tags(&mut self, node: DocNodeId, tags: Vec<String>) {
+        assert!(node < self.nodes.len());
+        assert!(node != DOC_NODE_ROOT_ID);
+        self.nodes[node].tags = tags;
+    }
+
+    pub fn add_keyword(&mut self, key: String, value: String) {
+        self.keywords.push((key, value));
+    }
+
+    ///values of every `#+KEY:` line with the key, case-insensitive
+    pub fn keywords(&self, key: &str) -> Vec<&str> {
+        return self
+            .keywords
+            .iter()
+            .filter(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
+            .collect();
+    }
+
+    pub fn add_example(&mut self, name: String, text: String) {
+        self.examples.push((name, text));
+    }
+
+    ///text of the first example block with the name
+    pub fn example(&self, name: &str) -> Option<&str> {
+        return self
+            .examples
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, text)| text.as_str());
+    }
+
+    pub fn add_unscheduled(&mut self, linum: usize, line: String) {
+        self.unscheduled.push((linum, line));
+    }
+
+    ///SCHEDULED lines with a timestamp orgjob does not understand, like the
+    ///repeaters of org-habit or diary sexps, which leave their heading unscheduled
+    pub fn unscheduled(&self) -> &[(usize, String)] {
+        return &self.unscheduled;
+    }
+
     pub fn len(&self) -> usize {
         return self.nodes.len() - 1;
     }
@@ -85,8 +247,17 @@ impl CodeDoc {
         return &self.nodes[node];
     }
 
-    ///lookup matching nodes
+    ///lookup nodes whose names contain the keys in order along their path
+    #[cfg(test)]
     pub fn lookup_nodes(&self, start_node: DocNodeId, query: &[&str]) -> Vec<DocNodeId> {
+        let segments: Vec<NameMatch> = query
+            .iter()
+            .map(|k| NameMatch::Contains(k.to_string()))
+            .collect();
+        return self.lookup_path(start_node, &segments);
+    }
+
+    fn lookup_path(&self, start_node: DocNodeId, query: &[NameMatch]) -> Vec<DocNodeId> {
         assert!(start_node < self.nodes.len());
         let mut result = Vec::new();
 
@@ -99,11 +270,11 @@ impl CodeDoc {
 
             for child in 1..self.nodes.len() {
                 if self.parent[child] == start_node {
-                    if self.nodes[child].name.contains(key) {
-                        result.extend(self.lookup_nodes(child, &query[1..]));
-                        result.extend(self.lookup_nodes(child, query));
+                    if key.matches(&self.nodes[child].name) {
+                        result.extend(self.lookup_path(child, &query[1..]));
+                        result.extend(self.lookup_path(child, query));
                     } else {
-                        result.extend(self.lookup_nodes(child, query));
+                        result.extend(self.lookup_path(child, query));
                     }
                 }
             }
@@ -112,6 +283,58 @@ impl CodeDoc {
         return result;
     }
 
+    ///every level of the path has to match, the document itself may be left out
+    fn lookup_anchored(&self, start_node: DocNodeId, query: &[NameMatch]) -> Vec<DocNodeId> {
+        if query.is_empty() {
+            return vec![start_node];
+        }
+        let mut result = Vec::new();
+        for child in self.get_children(start_node) {
+            if query[0].matches(&self.nodes[child].name) {
+                result.extend(self.lookup_anchored(child, &query[1..]));
+            }
+            if self.nodes[child].level == 0 {
+                result.extend(self.lookup_anchored(child, query));
+            }
+        }
+        return result;
+    }
+
+    ///nodes matching a parsed job path, each once: a node the query reaches
+    ///along several paths is one match, not an ambiguous job
+    pub fn find(&self, query: &Query) -> Vec<DocNodeId> {
+        let mut result = match query.anchored {
+            true => self.lookup_anchored(DOC_NODE_ROOT_ID, &query.segments),
+            false => self.lookup_path(DOC_NODE_ROOT_ID, &query.segments),
+        };
+        let mut seen = Vec::new();
+        result.retain(|n| {
+            let first = !seen.contains(n);
+            seen.push(*n);
+            first
+        });
+        return result;
+    }
+
+    ///property of the node or of its closest ancestor that has it
+    pub fn get_inherited_property(&self, node: DocNodeId, key: &str) -> Option<&str> {
+        let mut n = node;
+        while n != DOC_NODE_ROOT_ID {
+            if let Some(v) = self.get_node(n).property(key) {
+                return Some(v);
+            }
+            n = self.parent[n];
+        }
+        return None;
+    }
+
+    pub fn get_children(&self, node: DocNodeId) -> Vec<DocNodeId> {
+        assert!(node < self.nodes.len());
+        return ((node + 1)..self.nodes.len())
+            .filter(|child| self.parent[*child] == node)
+            .collect();
+    }
+
     fn is_anscestor(&self, anscestor: DocNodeId, mut child: DocNodeId) -> bool {
         assert!(anscestor < self.nodes.len());
         assert!(anscestor != DOC_NODE_ROOT_ID);
@@ -124,7 +347,7 @@ impl CodeDoc {
         return child == anscestor;
     }
 
-    fn get_ancestors(&self, node: DocNodeId) -> Vec<DocNodeId> {
+    pub fn get_ancestors(&self, node: DocNodeId) -> Vec<DocNodeId> {
         assert!(node < self.nodes.len());
         assert!(node != DOC_NODE_ROOT_ID);
         let mut res = Vec::new();
@@ -136,7 +359,7 @@ impl CodeDoc {
         return res;
     }
 
-    fn get_descendants(&self, node: DocNodeId) -> Vec<DocNodeId> {
+    pub fn get_descendants(&self, node: DocNodeId) -> Vec<DocNodeId> {
         assert!(node < self.nodes.len());
         assert!(node != DOC_NODE_ROOT_ID);
         let mut res = Vec::new();
@@ -157,6 +380,113 @@ impl CodeDoc {
         return fullname;
     }
 
+    ///jobs whose output a node reads with `:stdin job:PATH` on a block of
+    ///the node or of an ancestor
+    pub fn get_stdin_jobs(&self, node: DocNodeId) -> Vec<&str> {
+        let mut nodes = self.get_ancestors(node);
+        nodes.push(node);
+        return nodes
+            .iter()
+            .flat_map(|n| self.get_node(*n).code_blocks.iter())
+            .filter_map(|cb| cb.stdin()?.strip_prefix("job:"))
+            .collect();
+    }
+
+    ///resolve the job paths listed in the DEPENDS property of a node, and
+    ///those it reads the output of
+    pub fn get_dependencies(
+        &self,
+        node: DocNodeId,
+        sep: &str,
+    ) -> Result<Vec<DocNodeId>, DependencyError> {
+        let mut deps = Vec::new();
+        let mut paths: Vec<&str> = match self.get_node(node).property("DEPENDS") {
+            Some(v) => v.split_whitespace().collect(),
+            None => Vec::new(),
+        };
+        paths.extend(self.get_stdin_jobs(node));
+        for path in paths {
+            let matches = match parse_query(path, sep) {
+                Ok(query) => self.find(&query),
+                Err(error) => return Err(DependencyError::InvalidQuery { node, error }),
+            };
+            match matches.len() {
+                0 => {
+                    return Err(DependencyError::Unresolved {
+                        node,
+                        query: path.to_string(),
+                    })
+                }
+                1 => {
+                    if !deps.contains(&matches[0]) {
+                        deps.push(matches[0]);
+                    }
+                }
+                _ => {
+                    return Err(DependencyError::Ambiguous {
+                        node,
+                        query: path.to_string(),
+                        matches,
+                    })
+                }
+            }
+        }
+        return Ok(deps);
+    }
+
+    ///dependencies of a node in topological order, each listed once, ending with the node itself
+    pub fn get_dependency_order(
+        &self,
+        node: DocNodeId,
+        sep: &str,
+    ) -> Result<Vec<DocNodeId>, DependencyError> {
+        let mut order = Vec::new();
+        let mut path = Vec::new();
+        self.visit_dependencies(node, sep, &mut path, &mut order)?;
+        return Ok(order);
+    }
+
+    fn visit_dependencies(
+        &self,
+        node: DocNodeId,
+        sep: &str,
+        path: &mut Vec<DocNodeId>,
+        order: &mut Vec<DocNodeId>,
+    ) -> Result<(), DependencyError> {
+        if order.contains(&node) {
+            return Ok(());
+        }
+        if let Some(pos) = path.iter().position(|n| *n == node) {
+            let mut nodes = path[pos..].to_vec();
+            nodes.push(node);
+            return Err(DependencyError::Cycle { nodes });
+        }
+        path.push(node);
+        for dep in self.get_dependencies(node, sep)? {
+            self.visit_dependencies(dep, sep, path, order)?;
+        }
+        path.pop();
+        order.push(node);
+        return Ok(());
+    }
+
+    ///heading and line of every code block of the node, its ancestors and its
+    ///descendants in document order, whatever their language
+    pub fn get_pipeline(&self, node: DocNodeId) -> Vec<(DocNodeId, usize)> {
+        let mut nodes = self.get_ancestors(node);
+        nodes.push(node);
+        nodes.extend(self.get_descendants(node));
+        return nodes
+            .into_iter()
+            .flat_map(|n| {
+                self.get_node(n)
+                    .code_blocks
+                    .iter()
+                    .map(move |cb| (n, cb.linum))
+            })
+            .collect();
+    }
+
     pub fn get_runnable_code(&self, node: DocNodeId, sep: &str) -> Vec<RunnableCode> {
         let mut nodes = Vec::new();
         nodes.extend(self.get_ancestors(node));
@@ -172,33 +502,96 @@ impl CodeDoc {
             }
         }
 
+        //only the job and its descendants declare the files of a run, not the ancestors
+        let mut owned = vec![node];
+        owned.extend(self.get_descendants(node));
+        let mut prop_creates = Vec::new();
+        let mut prop_sources = Vec::new();
+        for n in &owned {
+            let node = self.get_node(*n);
+            if let Some(v) = node.property("CREATES") {
+                prop_creates.extend(v.split_whitespace().map(|x| x.to_string()));
+            }
+            if let Some(v) = node.property("SOURCES") {
+                prop_sources.extend(v.split_whitespace().map(|x| x.to_string()));
+            }
+        }
+
         let mut result = Vec::new();
 
         for l in langs.iter() {
+            let mut params: Vec<Param> = Vec::new();
+            for n in &nodes {
+                let node = self.get_node(*n);
+                let mut decls = Vec::new();
+                if let Some(v) = node.property("ARGS") {
+                    decls.extend(parse_params(v));
+                }
+                for cb in node.code_blocks.iter().filter(|x| &x.interpreter == l) {
+                    for (k, v) in cb.header_args.iter() {
+                        if k.eq_ignore_ascii_case("var") {
+                            decls.extend(parse_params(v));
+                        }
+                    }
+                }
+                for p in decls {
+                    if !params.iter().any(|x| x.name == p.name) {
+                        params.push(p);
+                    }
+                }
+            }
+            for n in &nodes {
+                let node = self.get_node(*n);
+                if let Some(v) = node.property("CHOICES") {
+                    set_param_choices(&mut params, v);
+                }
+                if let Some(v) = node.property("SECRET") {
+                    set_param_secrets(&mut params, v);
+                }
+            }
+            let mut creates = prop_creates.clone();
+            let mut sources = prop_sources.clone();
+            for n in &owned {
+                for cb in self.get_node(*n).code_blocks.iter() {
+                    if &cb.interpreter != l {
+                        continue;
+                    }
+                    if let Some(v) = cb.header_arg("creates") {
+                        creates.extend(v.split_whitespace().map(|x| x.to_string()));
+                    }
+                    if let Some(v) = cb.header_arg("sources") {
+                        sources.extend(v.split_whitespace().map(|x| x.to_string()));
+                    }
+                }
+            }
             let mut blocks = Vec::new();
+            let mut origin = Vec::new();
             for n in &nodes {
                 let fullname = self.get_fullname(*n);
                 if sep != "" {
-                    blocks.push(format!("echo {} start... 1>&2\n", fullname.join(sep)));
+                    blocks.extend(log_line(l, &format!("{} start...", fullname.join(sep))));
+                }
+                for cb in self.get_node(*n).code_blocks.iter() {
+                    if &cb.interpreter == l {
+                        blocks.push(cb.code.clone());
+                        origin.push((*n, cb.linum));
+                    }
                 }
-                blocks.extend(
-                    self.get_node(*n)
-                        .code_blocks
-                        .iter()
-                        .filter(|x| &x.interpreter == l)
-                        .map(|x| x.code.clone()),
-                );
                 if sep != "" {
-                    blocks.push(format!("echo {} done! 1>&2\n", fullname.join(sep)));
+                    blocks.extend(log_line(l, &format!("{} done!", fullname.join(sep))));
                 }
             }
             if sep != "" {
-                blocks.push(format!("echo ALL done! 1>&2\n"));
+                blocks.extend(log_line(l, "ALL done!"));
             }
             result.push(RunnableCode {
                 interpreter: l.to_string(),
                 fullname: self.get_fullname(node),
                 code: blocks,
+                creates,
+                sources,
+                params,
+                origin,
             })
         }
 
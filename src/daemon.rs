@@ -0,0 +1,274 @@
+use super::code_doc::*;
+use super::org_parser::*;
+use super::query::*;
+use super::runner::*;
+use super::scheduler::*;
+use super::stamp::*;
+use super::timestamp::*;
+use regex::Regex;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{BufReader, Error, ErrorKind, Result};
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::thread::sleep;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const POLL: Duration = Duration::from_secs(1);
+
+///a heading with a SCHEDULED timestamp, and when it runs next
+struct Entry {
+    ///full name of the heading, and that joined with sep for messages
+    name: Vec<String>,
+    job: String,
+    scheduled: Timestamp,
+    ///none while it runs and once it is not due any more
+    next: Option<i64>,
+}
+
+///a run started by the daemon
+struct DaemonRun {
+    name: Vec<String>,
+    job: String,
+    child: Child,
+    ///the time it was due
+    fired: i64,
+    started: i64,
+}
+
+fn now() -> i64 {
+    return SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64;
+}
+
+fn load(org_file: &Path) -> Result<CodeDoc> {
+    let f = File::open(org_file)?;
+    return parse_org_doc(&mut BufReader::new(f), "doc".to_string(), "bash")
+        .map_err(|e| Error::new(ErrorKind::InvalidData, format!("{:?}", e)));
+}
+
+///full name and timestamp of every heading with a SCHEDULED line
+pub fn scheduled_jobs(doc: &CodeDoc) -> Vec<(Vec<String>, Timestamp)> {
+    let mut jobs = Vec::new();
+    for n in (DOC_NODE_ROOT_ID + 1)..=doc.len() {
+        if let Some(ts) = doc.get_node(n).scheduled() {
+            jobs.push((doc.get_fullname(n), ts.clone()));
+        }
+    }
+    return jobs;
+}
+
+fn print_next(job: &str, e: &Entry) {
+    match e.next {
+        Some(t) => println!("{}: next run {}", job, format_local(t)),
+        None => println!("{}: not due any more", job),
+    }
+}
+
+///the entries for the jobs of a freshly loaded org file. Those whose
+///timestamp did not change keep when they run next, the others are due the
+///first time their timestamp comes after now.
+fn reschedule(
+    old: Vec<Entry>,
+    jobs: Vec<(Vec<String>, Timestamp)>,
+    sep: &str,
+    now: i64,
+) -> Vec<Entry> {
+    for e in old.iter() {
+        if !jobs.iter().any(|(name, _)| *name == e.name) {
+            println!("{}: not scheduled any more", e.job);
+        }
+    }
+    let mut entries = Vec::new();
+    for (name, scheduled) in jobs {
+        let job = name.join(sep);
+        match old
+            .iter()
+            .find(|e| e.name == name && e.scheduled == scheduled)
+        {
+            Some(e) => entries.push(Entry {
+                name,
+                job,
+                scheduled,
+                next: e.next,
+            }),
+            None => {
+                let e = Entry {
+                    next: scheduled.first_after(now),
+                    name,
+                    job,
+                    scheduled,
+                };
+                print_next(&e.job, &e);
+                entries.push(e);
+            }
+        }
+    }
+    return entries;
+}
+
+///add an entry to the top of the LOGBOOK drawer of a heading, which goes
+///after its planning line and property drawer and is made when missing
+pub fn add_logbook_entry(org_file: &Path, name: &[String], sep: &str, entry: &str) -> Result<()> {
+    let doc = load(org_file)?;
+    let linum = ((DOC_NODE_ROOT_ID + 1)..=doc.len())
+        .find(|n| doc.get_fullname(*n) == name)
+        .map(|n| doc.get_node(n).linum())
+        .filter(|linum| *linum > 0)
+        .ok_or_else(|| {
+            Error::new(
+                ErrorKind::NotFound,
+                format!("no heading {}", name.join(sep)),
+            )
+        })?;
+    let text = fs::read_to_string(org_file)?;
+    let mut lines: Vec<&str> = text.lines().collect();
+    let planning_re = Regex::new(PLANNING_RE).unwrap();
+    //linum counts from 1, so this is the line after the heading
+    let mut i = linum;
+    while i < lines.len() && planning_re.is_match(lines[i]) {
+        i += 1;
+    }
+    let is =
+        |i: usize, drawer: &str| i < lines.len() && lines[i].trim().eq_ignore_ascii_case(drawer);
+    if is(i, ":PROPERTIES:") {
+        while i < lines.len() && !is(i, ":END:") {
+            i += 1;
+        }
+        i += 1;
+    }
+    if is(i, ":LOGBOOK:") {
+        lines.insert(i + 1, entry);
+    } else {
+        lines.splice(i..i, [":LOGBOOK:", entry, ":END:"].iter().cloned());
+    }
+    let mut out = lines.join("\n");
+    if text.ends_with('\n') {
+        out.push('\n');
+    }
+    return fs::write(org_file, out);
+}
+
+///the arguments of `orgjob run` for a heading: args with the action and the
+///full name in exact segments, so that no other heading matches, before the
+///job arguments after `--`
+pub fn run_args(args: &[OsString], name: &[String], sep: &str) -> Vec<OsString> {
+    let end = args.iter().position(|a| a == "--").unwrap_or(args.len());
+    let mut result = args[..end].to_vec();
+    result.push("run".into());
+    result.push(exact_query(name, sep).into());
+    result.extend_from_slice(&args[end..]);
+    return result;
+}
+
+///run the headings of the org file with a SCHEDULED timestamp when it comes,
+///each through `orgjob run` with args in front of the action. A heading whose
+///last run is still going waits for it. The org file is read again when it
+///changes; with logbook every run is noted in the heading's LOGBOOK.
+///SIGINT or SIGTERM stop the runs and the daemon, the exit code then tells which.
+pub fn run_daemon(
+    org_file: &Path,
+    sep: &str,
+    program: &Path,
+    args: &[OsString],
+    logbook: bool,
+) -> Result<i32> {
+    catch_signals();
+    let mut loaded = None;
+    let mut entries: Vec<Entry> = Vec::new();
+    let mut runs: Vec<DaemonRun> = Vec::new();
+    loop {
+        let modified = mtime(org_file);
+        if modified != loaded {
+            loaded = modified;
+            match load(org_file) {
+                Ok(doc) => {
+                    for (linum, line) in doc.unscheduled() {
+                        println!(
+                            "{}:{}: not scheduled, invalid timestamp: {}",
+                            org_file.display(),
+                            linum,
+                            line.trim()
+                        );
+                    }
+                    entries = reschedule(entries, scheduled_jobs(&doc), sep, now());
+                }
+                Err(e) => println!("failed to load {}: {}", org_file.display(), e),
+            }
+        }
+        let mut i = 0;
+        while i < runs.len() {
+            let status = match runs[i].child.try_wait()? {
+                Some(status) => exit_status(status),
+                None => {
+                    i += 1;
+                    continue;
+                }
+            };
+            let run = runs.remove(i);
+            let finished = now();
+            println!("{} {}: {}", format_local(finished), run.job, status);
+            if logbook {
+                let entry = format!(
+                    "- Ran [{}]--[{}]: {}",
+                    format_local(run.started),
+                    format_local(finished),
+                    status
+                );
+                if let Err(e) = add_logbook_entry(org_file, &run.name, sep, &entry) {
+                    println!("failed to log {} in {}: {}", run.job, org_file.display(), e);
+                }
+            }
+            if let Some(e) = entries.iter_mut().find(|e| e.name == run.name) {
+                e.next = e.scheduled.next_run(run.fired, finished);
+                print_next(&run.job, e);
+            }
+        }
+        let t = now();
+        for e in entries.iter_mut() {
+            let fired = match e.next {
+                Some(next) if next <= t => next,
+                _ => continue,
+            };
+            if runs.iter().any(|r| r.name == e.name) {
+                continue;
+            }
+            println!("{} {}: started", format_local(t), e.job);
+            match Command::new(program)
+                .args(run_args(args, &e.name, sep))
+                .stdin(Stdio::null())
+                .spawn()
+            {
+                Ok(child) => {
+                    e.next = None;
+                    runs.push(DaemonRun {
+                        name: e.name.clone(),
+                        job: e.job.clone(),
+                        child,
+                        fired,
+                        started: t,
+                    });
+                }
+                Err(err) => {
+                    println!("failed to run {}: {}", e.job, err);
+                    e.next = e.scheduled.next_run(fired, t);
+                    print_next(&e.job, e);
+                }
+            }
+        }
+        if let Some(sig) = take_signal() {
+            for r in runs.iter_mut() {
+                unsafe {
+                    libc::kill(r.child.id() as libc::pid_t, libc::SIGTERM);
+                }
+            }
+            for r in runs.iter_mut() {
+                r.child.wait()?;
+            }
+            return Ok(128 + sig);
+        }
+        sleep(POLL);
+    }
+}
@@ -0,0 +1,101 @@
+use std::fmt;
+
+#[derive(PartialEq, Clone, Debug)]
+pub enum DotenvError {
+    InvalidLine { linum: usize, line: String },
+    QuoteNotClosed { linum: usize, line: String },
+}
+
+impl fmt::Display for DotenvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DotenvError::InvalidLine { linum, line } => {
+                write!(f, "line {}: expected KEY=VALUE: {}", linum, line)
+            }
+            DotenvError::QuoteNotClosed { linum, line } => {
+                write!(f, "line {}: quote not closed: {}", linum, line)
+            }
+        }
+    }
+}
+
+fn is_env_name(s: &str) -> bool {
+    let mut chars = s.chars();
+    return match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    };
+}
+
+///parse `KEY=VALUE` lines as in a .env file. Blank lines and `#` comments are
+///skipped and a leading `export` is allowed. Values can be 'single quoted',
+///taken as they are, or "double quoted" with \n, \t, \" and \\ escapes;
+///unquoted values end at ` #`. Nothing is expanded.
+pub fn parse_dotenv(text: &str) -> Result<Vec<(String, String)>, DotenvError> {
+    let mut vars = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let linum = i + 1;
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        let trimmed = trimmed.strip_prefix("export ").unwrap_or(trimmed);
+        let (key, raw) = match trimmed.find('=') {
+            Some(i) => (trimmed[..i].trim(), trimmed[i + 1..].trim()),
+            None => {
+                return Err(DotenvError::InvalidLine {
+                    linum,
+                    line: line.to_string(),
+                })
+            }
+        };
+        if !is_env_name(key) {
+            return Err(DotenvError::InvalidLine {
+                linum,
+                line: line.to_string(),
+            });
+        }
+        let not_closed = || DotenvError::QuoteNotClosed {
+            linum,
+            line: line.to_string(),
+        };
+        let value = if let Some(rest) = raw.strip_prefix('\'') {
+            match rest.find('\'') {
+                Some(end) => rest[..end].to_string(),
+                None => return Err(not_closed()),
+            }
+        } else if let Some(rest) = raw.strip_prefix('"') {
+            let mut value = String::new();
+            let mut chars = rest.chars();
+            let mut closed = false;
+            while let Some(c) = chars.next() {
+                match c {
+                    '"' => {
+                        closed = true;
+                        break;
+                    }
+                    '\\' => match chars.next() {
+                        Some('n') => value.push('\n'),
+                        Some('t') => value.push('\t'),
+                        Some(c) => value.push(c),
+                        None => value.push('\\'),
+                    },
+                    c => value.push(c),
+                }
+            }
+            if !closed {
+                return Err(not_closed());
+            }
+            value
+        } else {
+            match raw.find(" #") {
+                Some(i) => raw[..i].trim_end().to_string(),
+                None => raw.to_string(),
+            }
+        };
+        vars.push((key.to_string(), value));
+    }
+    return Ok(vars);
+}
@@ -0,0 +1,344 @@
+use super::params::*;
+use super::runner::*;
+use super::scheduler::*;
+use std::path::{Path, PathBuf};
+
+const RUN_STEP: &str = r###"exec 3<&0
+orgjob_step() {
+    local interpreter="$1"
+    shift
+    local script
+    script="$(mktemp)" || exit 1
+    cat > "$script"
+    ${orgjob_timeout:+timeout -k "$orgjob_kill_after" "$orgjob_timeout"} \
+        ${orgjob_nice:+nice -n "$orgjob_nice"} "$interpreter" "$script" "$@" <&3
+    local status=$?
+    rm -f "$script"
+    return $status
+}
+"###;
+
+pub fn quote(s: &str) -> String {
+    return format!("'{}'", s.replace('\'', r"'\''"));
+}
+
+///a heredoc delimiter that does not occur as a line of the code
+fn delimiter(code: &str) -> String {
+    let mut delim = "ORGJOB_EOF".to_string();
+    let mut i = 0;
+    while code.lines().any(|l| l == delim) {
+        i += 1;
+        delim = format!("ORGJOB_EOF{}", i);
+    }
+    return delim;
+}
+
+///path as seen from dir, both absolute
+fn relative_path(path: &Path, dir: &Path) -> PathBuf {
+    let mut path_parts = path.components().peekable();
+    let mut dir_parts = dir.components().peekable();
+    while let (Some(a), Some(b)) = (path_parts.peek(), dir_parts.peek()) {
+        if a != b {
+            break;
+        }
+        path_parts.next();
+        dir_parts.next();
+    }
+    let mut rel: PathBuf = dir_parts.map(|_| "..").collect();
+    rel.extend(path_parts);
+    return rel;
+}
+
+///where the script and the org file it was exported from are
+pub struct ExportPlace<'a> {
+    ///the directory the script is written to
+    pub script_dir: &'a Path,
+    ///paths in here are found from the script's directory, so that the
+    ///script and the org file can move together
+    pub base: &'a Path,
+}
+
+impl ExportPlace<'_> {
+    ///bash code for a path, relative to the script's directory when it is
+    ///one of the org file's
+    fn path(&self, path: &Path, org_path: bool) -> String {
+        if !org_path || path.is_relative() {
+            return quote(&path.to_string_lossy());
+        }
+        let rel = relative_path(path, self.script_dir);
+        if rel.as_os_str().is_empty() {
+            return "\"$orgjob_here\"".to_string();
+        }
+        return format!("\"$orgjob_here\"/{}", quote(&rel.to_string_lossy()));
+    }
+
+    ///a directory or input file of a task, one of the org file's when under base
+    fn dir_path(&self, path: &Path) -> String {
+        return self.path(path, path.starts_with(self.base));
+    }
+}
+
+///ORGJOB_FILE and ORGJOB_DIR are found from the script's directory
+fn env_prefix(env: &[(String, String)], place: &ExportPlace) -> String {
+    return env
+        .iter()
+        .map(|(k, v)| match k.as_str() {
+            "ORGJOB_FILE" | "ORGJOB_DIR" => format!("{}={} ", k, place.path(Path::new(v), true)),
+            _ => format!("{}={} ", k, quote(v)),
+        })
+        .collect();
+}
+
+///bash code that runs one attempt of a task after another until it succeeds
+///or runs out of attempts, as the scheduler does for RETRIES
+fn retry_code(name: &str, policy: &RetryPolicy, step: &str) -> String {
+    let delays: Vec<String> = (1..=policy.retries)
+        .map(|a| format!("{}", policy.delay_after(a).as_secs_f64()))
+        .collect();
+    let mut out = format!(
+        "orgjob_delays=({})\norgjob_attempt=1\nwhile :; do\n",
+        delays.join(" ")
+    );
+    out.push_str(step);
+    out.push_str("status=$?\nif [ $status -eq 0 ] || [ $orgjob_attempt -gt ${#orgjob_delays[@]} ]; then\n    break\nfi\n");
+    if !policy.exit_codes.is_empty() {
+        let codes: Vec<String> = policy.exit_codes.iter().map(|c| c.to_string()).collect();
+        out.push_str(&format!(
+            "case $status in\n    {}) ;;\n    *) break ;;\nesac\n",
+            codes.join("|")
+        ));
+    }
+    out.push_str(&format!(
+        "orgjob_delay=\"${{orgjob_delays[$((orgjob_attempt - 1))]}}\"\necho {}\"$orgjob_attempt of {} exited with $status, retrying in ${{orgjob_delay}}s\" >&2\nsleep \"$orgjob_delay\"\norgjob_attempt=$((orgjob_attempt + 1))\ndone\n(exit $status)\n",
+        quote(&format!("{}: attempt ", name)),
+        policy.retries + 1
+    ));
+    return out;
+}
+
+///parse the job's parameters from the script arguments like `run` does
+fn parse_params_code(job: &str, params: &[Param]) -> String {
+    let mut out = String::new();
+    let help = params_help(job, params);
+    let delim = delimiter(&help);
+    out.push_str(&format!(
+        "orgjob_usage() {{\n    cat <<'{}'\n{}{}\n}}\n",
+        delim, help, delim
+    ));
+    for p in params {
+        match &p.default {
+            Some(d) => out.push_str(&format!("orgjob_param_{}={}\n", p.name, quote(d))),
+            None => out.push_str(&format!("unset orgjob_param_{}\n", p.name)),
+        }
+    }
+    out.push_str("orgjob_args=()\nwhile [ $# -gt 0 ]; do\n    case \"$1\" in\n");
+    for p in params {
+        out.push_str(&format!(
+            "        --{0})\n            if [ $# -lt 2 ]; then\n                echo {1} >&2\n                exit 1\n            fi\n            orgjob_param_{0}=\"$2\"\n            shift 2 ;;\n",
+            p.name,
+            quote(&format!("no value for parameter: --{}", p.name))
+        ));
+        out.push_str(&format!(
+            "        --{0}=*)\n            orgjob_param_{0}=\"${{1#*=}}\"\n            shift ;;\n",
+            p.name
+        ));
+    }
+    out.push_str(concat!(
+        "        -h|--help)\n            orgjob_usage\n            exit 0 ;;\n",
+        "        --)\n            shift\n            break ;;\n",
+        "        --*)\n            echo \"unknown parameter: ${1%%=*}\" >&2\n            exit 1 ;;\n",
+        "        *)\n            orgjob_args+=(\"$1\")\n            shift ;;\n",
+        "    esac\ndone\nset -- \"${orgjob_args[@]}\" \"$@\"\n",
+    ));
+    for p in params {
+        let var = format!("orgjob_param_{}", p.name);
+        let mut allowed: Vec<String> = p.choices.iter().map(|c| quote(c)).collect();
+        if p.default.is_none() {
+            let read = format!(
+                "read -r{} -p {} {} || exit 1{}",
+                if p.secret { " -s" } else { "" },
+                quote(&format!("{}: ", p.name)),
+                var,
+                if p.secret { "\n        echo >&2" } else { "" }
+            );
+            out.push_str(&format!(
+                "if [ -z \"${{{0}+x}}\" ]; then\n    if [ ! -t 0 ]; then\n        echo {1} >&2\n        exit 1\n    fi\n",
+                var,
+                quote(&ParamError::NoTerminal(p.name.clone()).to_string())
+            ));
+            if p.choices.is_empty() {
+                out.push_str(&format!(
+                    "    while [ -z \"${0}\" ]; do\n        {1}\n    done\n",
+                    var, read
+                ));
+            } else {
+                out.push_str(&format!(
+                    "    echo {} >&2\n    while :; do\n        {}\n        case \"${}\" in\n            {}) break ;;\n        esac\n        echo {} >&2\n    done\n",
+                    quote(&format!("choices: {}", p.choices.join(", "))),
+                    read,
+                    var,
+                    allowed.join("|"),
+                    quote(&format!("choose one of: {}", p.choices.join(", ")))
+                ));
+            }
+            out.push_str("fi\n");
+        }
+        if !p.choices.is_empty() {
+            if let Some(d) = &p.default {
+                allowed.push(quote(d));
+            }
+            out.push_str(&format!(
+                "case \"${0}\" in\n    {1}) ;;\n    *)\n        echo \"invalid value for --{2}: ${0}\" >&2\n        exit 1 ;;\nesac\n",
+                var,
+                allowed.join("|"),
+                p.name
+            ));
+        }
+    }
+    return out;
+}
+
+///ulimit commands for the resource limits of a task, as `run` sets them
+fn ulimit_code(limits: &Limits) -> String {
+    let mut out = String::new();
+    if let Some(bytes) = limits.memory {
+        out.push_str(&format!("ulimit -v {} || exit 1\n", bytes / 1024));
+    }
+    if let Some(secs) = limits.cpu_seconds {
+        out.push_str(&format!(
+            "ulimit -H -t {} && ulimit -S -t {} || exit 1\n",
+            secs + KILL_GRACE.as_secs(),
+            secs
+        ));
+    }
+    if let Some(bytes) = limits.file_size {
+        out.push_str(&format!("ulimit -f {} || exit 1\n", bytes / 1024));
+    }
+    return out;
+}
+
+///a bash script that runs the tasks one after another the way `run` does;
+///the arguments of the script are matched against the job's parameters and
+///passed to the tasks of the job itself, those from first on. Output that
+///later tasks read goes through temp files.
+pub fn export_script(
+    org_file: &str,
+    job: &str,
+    tasks: &[Task],
+    first: usize,
+    params: &[Param],
+    place: &ExportPlace,
+) -> String {
+    let mut out = String::from("#!/usr/bin/env bash\n");
+    out.push_str(&format!("# exported by orgjob from {}\n", org_file));
+    out.push_str(&format!("# job: {}\n", job));
+    if first > 0 {
+        let deps: Vec<&str> = tasks[..first].iter().map(|t| t.name.as_str()).collect();
+        out.push_str(&format!("# dependencies: {}\n", deps.join(", ")));
+    }
+    out.push('\n');
+    out.push_str("orgjob_here=\"$(cd \"$(dirname \"$0\")\" && pwd)\" || exit 1\n");
+    if tasks.iter().any(|t| t.timeout.is_some()) {
+        out.push_str(&format!("orgjob_kill_after={}s\n", KILL_GRACE.as_secs()));
+    }
+    out.push_str(RUN_STEP);
+    if !params.is_empty() {
+        out.push('\n');
+        out.push_str(&parse_params_code(job, params));
+    }
+    let read_by_others = |i: usize| {
+        tasks
+            .iter()
+            .any(|t| t.stdin == Some(TaskInput::Output(i)) || t.inputs.iter().any(|(_, j)| *j == i))
+    };
+    let captured: Vec<usize> = (0..tasks.len()).filter(|i| read_by_others(*i)).collect();
+    if !captured.is_empty() {
+        out.push('\n');
+        for i in captured.iter() {
+            out.push_str(&format!("orgjob_out_{}=\"$(mktemp)\" || exit 1\n", i));
+        }
+        let files: Vec<String> = captured
+            .iter()
+            .map(|i| format!("\"$orgjob_out_{}\"", i))
+            .collect();
+        out.push_str(&format!("trap 'rm -f {}' EXIT\n", files.join(" ")));
+    }
+    for (i, t) in tasks.iter().enumerate() {
+        let delim = delimiter(&t.code);
+        let last = i + 1 == tasks.len();
+        out.push_str(&format!("\n# {}\n", t.name));
+        let mut env = env_prefix(&t.env, place);
+        if i >= first {
+            for p in params {
+                env.push_str(&format!("{0}=\"$orgjob_param_{0}\" ", p.name));
+            }
+        }
+        for (name, j) in t.inputs.iter() {
+            env.push_str(&format!("{}=\"$(<\"$orgjob_out_{}\")\" ", name, j));
+        }
+        if let Some(n) = t.limits.nice {
+            env.push_str(&format!("orgjob_nice={} ", n));
+        }
+        if let Some(d) = t.timeout {
+            env.push_str(&format!("orgjob_timeout={}s ", d.as_secs_f64()));
+        }
+        let limits = ulimit_code(&t.limits);
+        let subshell = t.dir.is_some() || !limits.is_empty();
+        if subshell {
+            out.push_str("(\n");
+        }
+        if let Some(dir) = &t.dir {
+            out.push_str(&format!("cd {} || exit 1\n", place.dir_path(dir)));
+        }
+        out.push_str(&limits);
+        //the interpreter reads fd 3, the script's own stdin unless the task has one
+        let stdin = match &t.stdin {
+            Some(TaskInput::Output(j)) => format!(" 3< \"$orgjob_out_{}\"", j),
+            Some(TaskInput::File(path)) => format!(" 3< {}", place.dir_path(path)),
+            Some(TaskInput::Example { text, .. }) => format!(" 3< <(printf %s {})", quote(text)),
+            None => String::new(),
+        };
+        let stdout = if captured.contains(&i) {
+            format!(" > \"$orgjob_out_{}\"", i)
+        } else {
+            String::new()
+        };
+        let mut step = format!(
+            "{}orgjob_step {}{}{}{} <<'{}'\n",
+            env,
+            quote(&t.interpreter),
+            if i >= first { " \"$@\"" } else { "" },
+            stdin,
+            stdout,
+            delim
+        );
+        step.push_str(&t.code);
+        if !t.code.ends_with('\n') {
+            step.push('\n');
+        }
+        step.push_str(&delim);
+        step.push('\n');
+        match &t.retry {
+            Some(policy) => out.push_str(&retry_code(&t.name, policy, &step)),
+            None => out.push_str(&step),
+        }
+        if subshell {
+            out.push_str(")\n");
+        }
+        if captured.contains(&i) {
+            out.push_str(&format!(
+                "status=$?\ncat \"$orgjob_out_{}\"\n(exit $status)\n",
+                i
+            ));
+        }
+        if last {
+            out.push_str("exit $?\n");
+        } else {
+            out.push_str(&format!(
+                "status=$?\nif [ $status -ne 0 ]; then\n    echo {} >&2\n    exit $status\nfi\n",
+                quote(&format!("dependency failed: {}", t.name))
+            ));
+        }
+    }
+    return out;
+}
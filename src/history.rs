@@ -0,0 +1,304 @@
+use super::mask::*;
+use super::scheduler::*;
+use super::stamp::*;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Result, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+///where run logs are kept: $XDG_STATE_HOME/orgjob, or ~/.local/state/orgjob
+pub fn state_dir() -> Option<PathBuf> {
+    if let Some(dir) = env::var_os("XDG_STATE_HOME").filter(|d| !d.is_empty()) {
+        return Some(PathBuf::from(dir).join("orgjob"));
+    }
+    let home = env::var_os("HOME").filter(|d| !d.is_empty())?;
+    return Some(PathBuf::from(home).join(".local/state/orgjob"));
+}
+
+///runs of the jobs of an org file are kept under a directory named after its full path
+pub fn doc_log_dir(state: &Path, org_file: &Path) -> PathBuf {
+    let path = fs::canonicalize(org_file).unwrap_or_else(|_| org_file.to_path_buf());
+    return state.join(safe_file_name(
+        path.to_string_lossy().trim_start_matches('/'),
+    ));
+}
+
+pub fn job_log_dir(doc_dir: &Path, job: &str) -> PathBuf {
+    return doc_dir.join(safe_file_name(job));
+}
+
+///UTC time like 2026-10-18T09:30:00.123Z
+pub fn format_time(t: SystemTime) -> String {
+    let d = t.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = d.as_secs();
+    let (days, rem) = ((secs / 86400) as i64, secs % 86400);
+    //civil from days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    return format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem / 60 % 60,
+        rem % 60,
+        d.subsec_millis()
+    );
+}
+
+///a new id for a run: the UTC start time, sortable, and the process id
+pub fn new_run_id() -> String {
+    let time: String = format_time(SystemTime::now())
+        .chars()
+        .filter(|c| *c != '-' && *c != ':')
+        .collect();
+    return format!("{}-{}", time, std::process::id());
+}
+
+///the record of one run of a job. The `run` file says what was run, when and
+///how it ended; every task that started has a directory with its script,
+///arguments, environment, status and the output of each attempt.
+pub struct RunLog {
+    pub dir: PathBuf,
+    started: SystemTime,
+}
+
+fn append(path: &Path, text: &str) -> Result<()> {
+    let mut f = OpenOptions::new().create(true).append(true).open(path)?;
+    return f.write_all(text.as_bytes());
+}
+
+///the output of the first attempt goes to stdout and stderr, that of a retry to stdout.N and stderr.N
+fn attempt_suffix(attempt: u32) -> String {
+    if attempt <= 1 {
+        return String::new();
+    }
+    return format!(".{}", attempt);
+}
+
+impl RunLog {
+    ///start the record of a run in a new directory under job_dir, named by the run id
+    pub fn create(job_dir: &Path, run_id: &str, job: &str, org_file: &Path) -> Result<RunLog> {
+        let started = SystemTime::now();
+        let org_file = fs::canonicalize(org_file).unwrap_or_else(|_| org_file.to_path_buf());
+        let name = safe_file_name(run_id);
+        fs::create_dir_all(job_dir)?;
+        let mut dir = job_dir.join(&name);
+        let mut n = 1;
+        loop {
+            match fs::create_dir(&dir) {
+                Ok(()) => break,
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                    n += 1;
+                    dir = job_dir.join(format!("{}-{}", name, n));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        append(
+            &dir.join("run"),
+            &format!(
+                "job: {}\nrun_id: {}\norg_file: {}\nstarted: {}\n",
+                job,
+                run_id,
+                org_file.display(),
+                format_time(started)
+            ),
+        )?;
+        return Ok(RunLog { dir, started });
+    }
+
+    fn task_dir(&self, index: usize, task: &Task) -> PathBuf {
+        return self
+            .dir
+            .join(format!("{:02}-{}", index + 1, safe_file_name(&task.name)));
+    }
+
+    ///write what the task is about to run and return the files for the
+    ///stdout and stderr of this attempt
+    pub fn start_task(
+        &self,
+        index: usize,
+        task: &Task,
+        attempt: u32,
+    ) -> Result<(PathBuf, PathBuf)> {
+        let dir = self.task_dir(index, task);
+        if attempt <= 1 {
+            fs::create_dir_all(&dir)?;
+            fs::write(dir.join("script"), &task.code)?;
+            let mut args = String::new();
+            for a in task.args.iter() {
+                args.push_str(&format!("{}\n", a));
+            }
+            fs::write(dir.join("args"), args)?;
+            let mut env = String::new();
+            for (k, v) in task.env.iter() {
+                if task.secrets.contains(k) {
+                    env.push_str(&format!("{}={}\n", k, MASK));
+                } else {
+                    env.push_str(&format!("{}={}\n", k, v));
+                }
+            }
+            fs::write(dir.join("env"), env)?;
+        }
+        let suffix = attempt_suffix(attempt);
+        return Ok((
+            dir.join(format!("stdout{}", suffix)),
+            dir.join(format!("stderr{}", suffix)),
+        ));
+    }
+
+    ///the attempt writes to the terminal, not to its stdout and stderr files
+    pub fn note_terminal(&self, index: usize, task: &Task, attempt: u32) -> Result<()> {
+        return append(
+            &self.task_dir(index, task).join("status"),
+            &format!("attempt {}: output went to the terminal\n", attempt),
+        );
+    }
+
+    pub fn record_attempt(
+        &self,
+        index: usize,
+        task: &Task,
+        attempt: u32,
+        status: &TaskStatus,
+        took: Duration,
+    ) -> Result<()> {
+        return append(
+            &self.task_dir(index, task).join("status"),
+            &format!("attempt {}: {} after {:?}\n", attempt, status, took),
+        );
+    }
+
+    ///what the given attempt of a task wrote to stdout and stderr
+    pub fn read_output(&self, index: usize, task: &Task, attempt: u32) -> (String, String) {
+        let dir = self.task_dir(index, task);
+        let suffix = attempt_suffix(attempt);
+        let read = |name: &str| {
+            let bytes = fs::read(dir.join(format!("{}{}", name, suffix))).unwrap_or_default();
+            return String::from_utf8_lossy(&bytes).to_string();
+        };
+        return (read("stdout"), read("stderr"));
+    }
+
+    ///write the final status of every task and of the whole run
+    pub fn finish(&self, tasks: &[Task], status: &[TaskStatus], code: i32) -> Result<()> {
+        let mut text = String::new();
+        for (i, (t, s)) in tasks.iter().zip(status.iter()).enumerate() {
+            text.push_str(&format!("task {:02}-{}: {}\n", i + 1, t.name, s));
+        }
+        let took = self.started.elapsed().unwrap_or_default();
+        text.push_str(&format!(
+            "finished: {}\nduration: {:.3}s\nstatus: {}\n",
+            format_time(SystemTime::now()),
+            took.as_secs_f64(),
+            code
+        ));
+        return append(&self.dir.join("run"), &text);
+    }
+}
+
+///a run as read back from its `run` file
+#[derive(PartialEq, Clone, Debug)]
+pub struct RunRecord {
+    pub dir: PathBuf,
+    pub job: String,
+    pub started: String,
+    pub duration: Option<String>,
+    ///exit code of the run, none if orgjob did not get to finish it
+    pub status: Option<i32>,
+}
+
+fn read_record(dir: &Path) -> Option<RunRecord> {
+    let text = fs::read_to_string(dir.join("run")).ok()?;
+    let mut record = RunRecord {
+        dir: dir.to_path_buf(),
+        job: String::new(),
+        started: String::new(),
+        duration: None,
+        status: None,
+    };
+    for line in text.lines() {
+        let (key, value) = match line.find(": ") {
+            Some(i) => (&line[..i], &line[i + 2..]),
+            None => continue,
+        };
+        match key {
+            "job" => record.job = value.to_string(),
+            "started" => record.started = value.to_string(),
+            "duration" => record.duration = Some(value.to_string()),
+            "status" => record.status = value.parse().ok(),
+            _ => {}
+        }
+    }
+    return Some(record);
+}
+
+fn subdirs(dir: &Path) -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.is_dir())
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    dirs.sort();
+    return dirs;
+}
+
+///recorded runs of one job, or of every job when job_dir is none, oldest first
+pub fn list_runs(doc_dir: &Path, job_dir: Option<&Path>) -> Vec<RunRecord> {
+    let job_dirs = match job_dir {
+        Some(d) => vec![d.to_path_buf()],
+        None => subdirs(doc_dir),
+    };
+    let mut runs: Vec<RunRecord> = job_dirs
+        .iter()
+        .flat_map(|d| subdirs(d))
+        .filter_map(|d| read_record(&d))
+        .collect();
+    runs.sort_by(|a, b| a.started.cmp(&b.started));
+    return runs;
+}
+
+///everything recorded about a run as one text: the run file, then the
+///status and output of each task
+pub fn read_run_log(run: &RunRecord) -> Result<String> {
+    let mut text = fs::read_to_string(run.dir.join("run"))?;
+    for task in subdirs(&run.dir) {
+        let name = task.file_name().unwrap().to_string_lossy().to_string();
+        text.push_str(&format!("\n=== {}\n", name));
+        if let Ok(status) = fs::read_to_string(task.join("status")) {
+            text.push_str(&status);
+        }
+        for attempt in 1.. {
+            let suffix = attempt_suffix(attempt);
+            let files = [format!("stdout{}", suffix), format!("stderr{}", suffix)];
+            if !files.iter().any(|f| task.join(f).exists()) {
+                break;
+            }
+            for f in files.iter() {
+                let content = fs::read_to_string(task.join(f)).unwrap_or_default();
+                if content.is_empty() {
+                    continue;
+                }
+                text.push_str(&format!("--- {}\n", f));
+                text.push_str(&content);
+                if !content.ends_with('\n') {
+                    text.push('\n');
+                }
+            }
+        }
+    }
+    return Ok(text);
+}
@@ -0,0 +1,170 @@
+///how declared job parameters reach the code of an interpreter
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum ParamStyle {
+    ///environment variables, which shells see as plain variables
+    Env,
+    ///environment variables copied into python globals
+    Python,
+}
+
+///how a long-lived interpreter of a `:session` is fed its blocks
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum SessionStyle {
+    ///a shell sourcing one file per block
+    Shell,
+    ///python executing one file per block in the same globals
+    Python,
+}
+
+///what orgjob knows about an interpreter named in a src block
+pub struct Interpreter {
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    pub extension: &'static str,
+    pub params: ParamStyle,
+    ///a line of code printing a progress message to stderr
+    pub log_line: fn(&str) -> String,
+    ///none when blocks cannot share a session
+    pub session: Option<SessionStyle>,
+    ///arguments before the script that make the interpreter show what it
+    ///runs, none when it has no such mode
+    pub trace: Option<&'static [&'static str]>,
+}
+
+fn log_echo(msg: &str) -> String {
+    return format!("echo {} 1>&2\n", msg);
+}
+
+///a python string literal with the text of s
+pub fn py_quote(s: &str) -> String {
+    let mut quoted = String::from("'");
+    for c in s.chars() {
+        match c {
+            '\\' => quoted.push_str("\\\\"),
+            '\'' => quoted.push_str("\\'"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() && (c as u32) < 0x100 => {
+                quoted.push_str(&format!("\\x{:02x}", c as u32))
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('\'');
+    return quoted;
+}
+
+fn log_python(msg: &str) -> String {
+    return format!(
+        "__import__(\"sys\").stderr.write({})\n",
+        py_quote(&format!("{}\n", msg))
+    );
+}
+
+///a single-quoted perl or ruby string literal with the text of s, in which
+///nothing is interpolated and only \ and ' are escaped
+pub fn single_quote(s: &str) -> String {
+    return format!("'{}'", s.replace('\\', "\\\\").replace('\'', "\\'"));
+}
+
+fn log_perl(msg: &str) -> String {
+    return format!("print STDERR {}, \"\\n\";\n", single_quote(msg));
+}
+
+fn log_ruby(msg: &str) -> String {
+    return format!("$stderr.puts({})\n", single_quote(msg));
+}
+
+///python's escapes work in javascript string literals too
+fn log_node(msg: &str) -> String {
+    return format!("console.error({});\n", py_quote(msg));
+}
+
+static INTERPRETERS: &[Interpreter] = &[
+    Interpreter {
+        name: "bash",
+        aliases: &["sh", "shell", "zsh"],
+        extension: "sh",
+        params: ParamStyle::Env,
+        log_line: log_echo,
+        session: Some(SessionStyle::Shell),
+        trace: Some(&["-x"]),
+    },
+    Interpreter {
+        name: "python",
+        aliases: &["python3", "python2"],
+        extension: "py",
+        params: ParamStyle::Python,
+        log_line: log_python,
+        session: Some(SessionStyle::Python),
+        trace: Some(&["-m", "trace", "--trace"]),
+    },
+    Interpreter {
+        name: "perl",
+        aliases: &[],
+        extension: "pl",
+        params: ParamStyle::Env,
+        log_line: log_perl,
+        session: None,
+        trace: None,
+    },
+    Interpreter {
+        name: "ruby",
+        aliases: &[],
+        extension: "rb",
+        params: ParamStyle::Env,
+        log_line: log_ruby,
+        session: None,
+        trace: None,
+    },
+    Interpreter {
+        name: "node",
+        aliases: &["js"],
+        extension: "js",
+        params: ParamStyle::Env,
+        log_line: log_node,
+        session: None,
+        trace: None,
+    },
+];
+
+pub fn find_interpreter(name: &str) -> Option<&'static Interpreter> {
+    return INTERPRETERS
+        .iter()
+        .find(|i| i.name == name || i.aliases.contains(&name));
+}
+
+///code printing a progress message, or nothing for unknown interpreters
+pub fn log_line(interpreter: &str, msg: &str) -> Option<String> {
+    return find_interpreter(interpreter).map(|i| (i.log_line)(msg));
+}
+
+pub fn session_style(interpreter: &str) -> Option<SessionStyle> {
+    return find_interpreter(interpreter).and_then(|i| i.session);
+}
+
+pub fn trace_args(interpreter: &str) -> Option<&'static [&'static str]> {
+    return find_interpreter(interpreter).and_then(|i| i.trace);
+}
+
+pub fn file_extension(interpreter: &str) -> &str {
+    return match find_interpreter(interpreter) {
+        Some(i) => i.extension,
+        None => interpreter,
+    };
+}
+
+///code to put in front of a script so that it sees its parameters natively;
+///the values themselves are passed as environment variables
+pub fn param_prelude(interpreter: &str, names: &[&str]) -> String {
+    let style = find_interpreter(interpreter).map_or(ParamStyle::Env, |i| i.params);
+    if names.is_empty() || style == ParamStyle::Env {
+        return String::new();
+    }
+    let mut prelude = String::from("import os as _orgjob_os\n");
+    for name in names {
+        prelude.push_str(&format!("{} = _orgjob_os.environ[{:?}]\n", name, name));
+    }
+    return prelude;
+}
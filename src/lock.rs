@@ -0,0 +1,149 @@
+use super::history::*;
+use super::stamp::*;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Error, ErrorKind, Read, Result, Seek, SeekFrom, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+///where the lock of a job is: locks of the jobs of an org file are kept
+///together under a directory named after its full path
+pub fn lock_path(state: &Path, org_file: &Path, key: &str) -> PathBuf {
+    return doc_log_dir(&state.join("locks"), org_file)
+        .join(format!("{}.lock", safe_file_name(key)));
+}
+
+///who holds a lock, as written in the lock file
+#[derive(PartialEq, Clone, Debug)]
+pub struct LockHolder {
+    pub pid: i32,
+    pub user: String,
+    pub started: String,
+}
+
+impl LockHolder {
+    fn parse(text: &str) -> Option<LockHolder> {
+        let mut holder = LockHolder {
+            pid: 0,
+            user: String::new(),
+            started: String::new(),
+        };
+        for line in text.lines() {
+            let (key, value) = match line.find(": ") {
+                Some(i) => (&line[..i], &line[i + 2..]),
+                None => continue,
+            };
+            match key {
+                "pid" => holder.pid = value.parse().ok()?,
+                "user" => holder.user = value.to_string(),
+                "started" => holder.started = value.to_string(),
+                _ => {}
+            }
+        }
+        if holder.pid <= 0 {
+            return None;
+        }
+        return Some(holder);
+    }
+
+    pub fn is_alive(&self) -> bool {
+        let r = unsafe { libc::kill(self.pid, 0) };
+        return r == 0 || Error::last_os_error().raw_os_error() == Some(libc::EPERM);
+    }
+}
+
+impl fmt::Display for LockHolder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "pid {}, user {}, started {}",
+            self.pid, self.user, self.started
+        )
+    }
+}
+
+fn current_user() -> String {
+    for key in ["USER", "LOGNAME"].iter() {
+        if let Some(user) = std::env::var_os(key).filter(|u| !u.is_empty()) {
+            return user.to_string_lossy().to_string();
+        }
+    }
+    return format!("uid {}", unsafe { libc::getuid() });
+}
+
+///how taking a lock went when it did not
+pub enum LockError {
+    ///somebody else holds it, and who when the lock file tells
+    Held(Option<LockHolder>),
+    Io(Error),
+}
+
+///an advisory lock on a file, held until it is released or orgjob exits.
+///The kernel lets go of it when its holder dies; the holder's details stay
+///in the file then, which is how the next one finds out about a stale lock.
+pub struct JobLock {
+    file: File,
+    ///the holder a stale lock was taken over from
+    pub stale: Option<LockHolder>,
+}
+
+impl JobLock {
+    ///take the lock at path; with wait, block until its holder releases it
+    pub fn acquire(path: &Path, wait: bool) -> std::result::Result<JobLock, LockError> {
+        let io = LockError::Io;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(io)?;
+        }
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(io)?;
+        let flags = if wait {
+            libc::LOCK_EX
+        } else {
+            libc::LOCK_EX | libc::LOCK_NB
+        };
+        loop {
+            if unsafe { libc::flock(file.as_raw_fd(), flags) } == 0 {
+                break;
+            }
+            let e = Error::last_os_error();
+            match e.kind() {
+                ErrorKind::Interrupted => continue,
+                ErrorKind::WouldBlock => {
+                    let mut text = String::new();
+                    let _ = file.read_to_string(&mut text);
+                    return Err(LockError::Held(LockHolder::parse(&text)));
+                }
+                _ => return Err(io(e)),
+            }
+        }
+        let mut text = String::new();
+        file.read_to_string(&mut text).map_err(io)?;
+        let stale = LockHolder::parse(&text).filter(|h| !h.is_alive());
+        file.set_len(0).map_err(io)?;
+        file.seek(SeekFrom::Start(0)).map_err(io)?;
+        let holder = format!(
+            "pid: {}\nuser: {}\nstarted: {}\n",
+            std::process::id(),
+            current_user(),
+            format_time(SystemTime::now())
+        );
+        file.write_all(holder.as_bytes()).map_err(io)?;
+        return Ok(JobLock { file, stale });
+    }
+
+    ///let go of the lock, leaving an empty file behind
+    pub fn release(self) -> Result<()> {
+        return self.file.set_len(0);
+    }
+}
+
+///what is in a lock file, none when nobody took it or its holder let go
+pub fn read_lock(path: &Path) -> Option<LockHolder> {
+    return LockHolder::parse(&fs::read_to_string(path).ok()?);
+}
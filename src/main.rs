@@ -1,39 +1,1444 @@
 extern crate clap;
-extern crate tempfile;
 
 use clap::{App, Arg};
+#[cfg(test)]
 use std::ffi::OsStr;
+use std::ffi::OsString;
 use std::fs::File;
-use std::io::{BufReader, Result, Write};
+use std::io::{BufReader, Result};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
 use std::process::exit;
+use std::time::{Duration, Instant};
 use std::vec::Vec;
 
 mod code_doc;
+mod daemon;
+mod dotenv;
+mod export;
+mod history;
+mod interpreter;
+mod lock;
+mod mask;
 mod org_parser;
+mod params;
+mod prompt;
+mod query;
+mod report;
+mod runner;
+mod scheduler;
+mod session;
+mod stamp;
+mod tangle;
 #[cfg(test)]
 mod tests;
+mod timestamp;
+mod watch;
 
 use code_doc::*;
+use daemon::*;
+use dotenv::*;
+use export::*;
+use history::*;
+use interpreter::*;
+use lock::*;
+use mask::*;
 use org_parser::*;
-use std::process::{Command, ExitStatus};
+use params::*;
+use prompt::*;
+use query::*;
+use report::*;
+use runner::*;
+use scheduler::*;
+use stamp::*;
+use tangle::*;
+use watch::*;
+
+///the code select_code would pick, none instead of exiting
+fn find_code<'a>(code: &'a [RunnableCode], lang: Option<&str>) -> Option<&'a RunnableCode> {
+    return match (code, lang) {
+        ([c], _) => Some(c),
+        (_, Some(lang)) => code.iter().find(|c| c.interpreter == lang),
+        _ => None,
+    };
+}
 
-fn run_code<S: AsRef<OsStr>>(interpreter: &str, code: &str, args: &[S]) -> Result<ExitStatus> {
-    let mut script_file = tempfile::NamedTempFile::new()?;
-    let mut args_vec = Vec::new();
-    let fname = script_file.path().as_os_str().to_owned();
-    args_vec.push(fname.as_os_str());
-    for arg in args {
-        args_vec.push(arg.as_ref());
+///pick the code to run for a node, exiting when the choice is missing or ambiguous
+fn select_code<'a, Q: std::fmt::Debug>(
+    doc: &CodeDoc,
+    node: DocNodeId,
+    code: &'a [RunnableCode],
+    query: &Q,
+    lang: Option<&str>,
+) -> &'a RunnableCode {
+    match code.len() {
+        0 => {
+            println!(
+                "no code avaiable in {} for {:?}",
+                doc.get_node(node).name(),
+                query
+            );
+            exit(1);
+        }
+        1 => &code[0],
+        _ => {
+            if let Some(lang) = lang {
+                match code.iter().find(|c| c.interpreter == lang) {
+                    Some(c) => c,
+                    None => {
+                        println!("no match for: {:?} with lang: {}", query, lang);
+                        exit(1);
+                    }
+                }
+            } else {
+                println!("mutliple languages in the matched block; use -l/--lang or --pipeline");
+                for c in code {
+                    println!("{}", c.interpreter);
+                }
+                exit(1);
+            }
+        }
     }
-    script_file.write_all(code.as_bytes())?;
-    script_file.flush()?;
-    let mut shell = Command::new(interpreter).args(&args_vec).spawn()?;
-    return shell.wait();
 }
 
-fn main() -> Result<()> {
-    let matches = App::new("Run code in org doc by hierarchy")
+fn print_dependency_error(doc: &CodeDoc, err: &DependencyError, sep: &str) {
+    match err {
+        DependencyError::Unresolved { node, query } => {
+            println!(
+                "no matches for dependency {} of {}",
+                query,
+                doc.get_fullname(*node).join(sep)
+            );
+        }
+        DependencyError::Ambiguous {
+            node,
+            query,
+            matches,
+        } => {
+            println!(
+                "multiple matches for dependency {} of {}",
+                query,
+                doc.get_fullname(*node).join(sep)
+            );
+            for n in matches {
+                println!("{}", doc.get_fullname(*n).join(sep));
+            }
+        }
+        DependencyError::Cycle { nodes } => {
+            let names: Vec<String> = nodes
+                .iter()
+                .map(|n| doc.get_fullname(*n).join(sep))
+                .collect();
+            println!("dependency cycle: {}", names.join(" -> "));
+        }
+        DependencyError::InvalidQuery { node, error } => {
+            println!(
+                "invalid dependency of {}: {}",
+                doc.get_fullname(*node).join(sep),
+                error
+            );
+        }
+    }
+}
+
+///dependency graph in DOT format, edges point from a dependency to its dependent
+fn dependency_graph(
+    doc: &CodeDoc,
+    roots: &[DocNodeId],
+    sep: &str,
+) -> std::result::Result<String, DependencyError> {
+    let mut out = String::from("digraph jobs {\n");
+    if roots.len() == 1 {
+        out.push_str(&format!(
+            "    {:?};\n",
+            doc.get_fullname(roots[0]).join(sep)
+        ));
+    }
+    let mut visited = Vec::new();
+    let mut pending: Vec<DocNodeId> = roots.to_vec();
+    pending.reverse();
+    while let Some(n) = pending.pop() {
+        if visited.contains(&n) {
+            continue;
+        }
+        visited.push(n);
+        let deps = doc.get_dependencies(n, sep)?;
+        let name = doc.get_fullname(n).join(sep);
+        for d in deps.iter() {
+            out.push_str(&format!(
+                "    {:?} -> {:?};\n",
+                doc.get_fullname(*d).join(sep),
+                name
+            ));
+        }
+        pending.extend(deps.iter().rev());
+    }
+    out.push_str("}\n");
+    return Ok(out);
+}
+
+fn print_dependency_graph(doc: &CodeDoc, roots: &[DocNodeId], sep: &str) {
+    match dependency_graph(doc, roots, sep) {
+        Ok(dot) => print!("{}", dot),
+        Err(e) => {
+            print_dependency_error(doc, &e, sep);
+            exit(1);
+        }
+    }
+}
+
+///settings shared by every job of a run
+struct RunSettings<'a> {
+    org_file: &'a Path,
+    lang: Option<&'a str>,
+    sep: &'a str,
+    ///split the job into one task per child heading
+    parallel: bool,
+    ///run even when the outputs are up to date
+    force: bool,
+    ///timeout for every job, overriding TIMEOUT properties
+    timeout: Option<Duration>,
+    ///what relative DIR properties and :dir header args are relative to
+    dir_base: &'a Path,
+    ///--env overrides
+    env: &'a [(String, String)],
+    ///id of this run, none when exporting
+    run_id: Option<&'a str>,
+    ///one task per code block in document order, each reading the output of the last
+    pipeline: bool,
+    ///--stdin-from, what the job reads instead of its :stdin
+    stdin_from: Option<&'a str>,
+    ///only describe the run, without asking for parameters
+    dry_run: bool,
+}
+
+fn build_check(
+    code: &RunnableCode,
+    args: &[String],
+    dir: &Path,
+    settings: &RunSettings,
+) -> Option<BuildCheck> {
+    if settings.force || code.creates.is_empty() {
+        return None;
+    }
+    //the files are where the job runs, so that the check does not depend on
+    //where orgjob is run
+    let path = |f: &String| dir.join(f);
+    return Some(BuildCheck {
+        creates: code.creates.iter().map(path).collect(),
+        sources: code.sources.iter().map(path).collect(),
+        stamp: stamp_path(settings.org_file, &code.fullname, settings.sep),
+        hash: code_hash(code, args),
+    });
+}
+
+///ORGJOB_FILE and ORGJOB_DIR, the absolute path of the org file and its directory
+fn org_env(org_file: &Path) -> Vec<(String, String)> {
+    let path = match std::fs::canonicalize(org_file) {
+        Ok(path) => path,
+        Err(_) => std::env::current_dir().unwrap_or_default().join(org_file),
+    };
+    let dir = path.parent().unwrap_or_else(|| Path::new("/"));
+    return vec![
+        (
+            "ORGJOB_FILE".to_string(),
+            path.to_string_lossy().to_string(),
+        ),
+        ("ORGJOB_DIR".to_string(), dir.to_string_lossy().to_string()),
+    ];
+}
+
+///set a variable, replacing an earlier value so that the last one wins
+fn set_env(env: &mut Vec<(String, String)>, key: &str, value: &str) {
+    env.retain(|(k, _)| k != key);
+    env.push((key.to_string(), value.to_string()));
+}
+
+///the files named by `#+ENV_FILE:` lines and by the ENV_FILE properties of
+///the node's ancestors and the node itself. Relative paths are relative to
+///the org file.
+fn env_file_paths(doc: &CodeDoc, node: DocNodeId, settings: &RunSettings) -> Vec<PathBuf> {
+    let mut files: Vec<&str> = Vec::new();
+    for value in doc.keywords("ENV_FILE") {
+        files.extend(value.split_whitespace());
+    }
+    let mut chain = doc.get_ancestors(node);
+    chain.push(node);
+    for n in chain {
+        if let Some(value) = doc.get_node(n).property("ENV_FILE") {
+            files.extend(value.split_whitespace());
+        }
+    }
+    let org_dir = match org_env(settings.org_file).pop() {
+        Some((_, dir)) => PathBuf::from(dir),
+        None => PathBuf::new(),
+    };
+    return files.iter().map(|f| org_dir.join(f)).collect();
+}
+
+///variables from the env files of the node, later files winning
+fn job_env_files(doc: &CodeDoc, node: DocNodeId, settings: &RunSettings) -> Vec<(String, String)> {
+    let mut env = Vec::new();
+    for path in env_file_paths(doc, node, settings) {
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) => {
+                println!("failed to read env file {}: {}", path.display(), e);
+                exit(1);
+            }
+        };
+        match parse_dotenv(&text) {
+            Ok(vars) => {
+                for (k, v) in vars {
+                    set_env(&mut env, &k, &v);
+                }
+            }
+            Err(e) => {
+                println!("invalid env file {}: {}", path.display(), e);
+                exit(1);
+            }
+        }
+    }
+    return env;
+}
+
+///working directory of a job: the closest `:dir` header arg of a block in the
+///job's language or DIR property, looking at the node first, then its
+///ancestors, and dir_base without one
+fn job_dir(doc: &CodeDoc, node: DocNodeId, interpreter: &str, settings: &RunSettings) -> PathBuf {
+    let mut n = node;
+    let mut dir = None;
+    while n != DOC_NODE_ROOT_ID && dir.is_none() {
+        let heading = doc.get_node(n);
+        dir = heading
+            .code_blocks()
+            .iter()
+            .filter(|cb| cb.interpreter == interpreter)
+            .find_map(|cb| cb.header_arg("dir"))
+            .or_else(|| heading.property("DIR"));
+        n = doc.get_parent(n).unwrap();
+    }
+    let dir = match dir {
+        Some(dir) => dir,
+        None => return settings.dir_base.to_path_buf(),
+    };
+    let path = match (dir.strip_prefix("~/"), std::env::var_os("HOME")) {
+        (Some(rest), Some(home)) => Path::new(&home).join(rest),
+        _ => PathBuf::from(dir),
+    };
+    return settings.dir_base.join(path);
+}
+
+///TIMEOUT property of the node or its closest ancestor that has one
+fn job_timeout(doc: &CodeDoc, node: DocNodeId) -> Option<Duration> {
+    let value = doc.get_inherited_property(node, "TIMEOUT")?;
+    match parse_duration(value) {
+        Some(d) => return Some(d),
+        None => {
+            println!(
+                "invalid TIMEOUT for {}: {}",
+                doc.get_fullname(node).join("."),
+                value
+            );
+            exit(1);
+        }
+    }
+}
+
+///the nodes matching a job path from the command line or the org file,
+///see parse_query
+fn find_jobs(doc: &CodeDoc, path: &str, sep: &str) -> Vec<DocNodeId> {
+    match parse_query(path, sep) {
+        Ok(query) => return doc.find(&query),
+        Err(e) => {
+            println!("{}", e);
+            exit(1);
+        }
+    }
+}
+
+///relative DIR properties and :dir header args are relative to this
+fn dir_base(org_file: &str, from_cwd: bool) -> Result<PathBuf> {
+    if from_cwd {
+        return std::env::current_dir();
+    }
+    let path = std::fs::canonicalize(org_file)?;
+    return Ok(path.parent().unwrap().to_path_buf());
+}
+
+///the arguments without a flag of orgjob's own, which the job's arguments
+///after `--` may have too
+fn without_option(args: Vec<OsString>, flag: &str) -> Vec<OsString> {
+    let end = args.iter().position(|a| a == "--").unwrap_or(args.len());
+    return args
+        .into_iter()
+        .enumerate()
+        .filter(|(i, a)| *i >= end || a != flag)
+        .map(|(_, a)| a)
+        .collect();
+}
+
+///what a run of the job depends on for --watch: the org file, then the env
+///files and SOURCES of the job and its dependencies as the org file has them
+///now. Only the org file when it cannot be parsed or the job is not found.
+fn watched_files(org_file: &Path, job: &str, sep: &str, dir_base: &Path) -> Vec<PathBuf> {
+    let mut files = vec![org_file.to_path_buf()];
+    let doc = match File::open(org_file) {
+        Ok(f) => match parse_org_doc(&mut BufReader::new(f), "doc".to_string(), "bash") {
+            Ok(doc) => doc,
+            Err(_) => return files,
+        },
+        Err(_) => return files,
+    };
+    let nodes = match parse_query(job, sep) {
+        Ok(query) => doc.find(&query),
+        Err(_) => return files,
+    };
+    let n = match nodes[..] {
+        [n] => n,
+        _ => return files,
+    };
+    let settings = RunSettings {
+        org_file,
+        lang: None,
+        sep,
+        parallel: false,
+        force: true,
+        timeout: None,
+        dir_base,
+        env: &[],
+        run_id: None,
+        pipeline: false,
+        stdin_from: None,
+        dry_run: false,
+    };
+    for node in doc.get_dependency_order(n, sep).unwrap_or_else(|_| vec![n]) {
+        files.extend(env_file_paths(&doc, node, &settings));
+        for c in doc.get_runnable_code(node, sep) {
+            let dir = job_dir(&doc, node, &c.interpreter, &settings);
+            files.extend(c.sources.iter().map(|f| dir.join(f)));
+        }
+    }
+    files.sort();
+    files.dedup();
+    return files;
+}
+
+///--trace needs every task to run a script in an interpreter with a trace mode
+fn check_trace(tasks: &[Task]) {
+    for t in tasks.iter() {
+        if t.session.is_some() {
+            println!("{}: --trace does not work with :session blocks", t.name);
+            exit(1);
+        }
+        if trace_args(&t.interpreter).is_none() {
+            println!("{}: {} has no trace mode", t.name, t.interpreter);
+            exit(1);
+        }
+    }
+}
+
+///an exported script cannot do what run does for sessions and env files,
+///which are read when the job runs, nor ask for the confirmation of CONFIRM
+///and :danger: jobs or take orgjob's LOCK
+fn check_export(doc: &CodeDoc, order: &[DocNodeId], tasks: &[Task], settings: &RunSettings) {
+    for t in tasks.iter() {
+        if t.session.is_some() {
+            println!("{}: export does not work with :session blocks", t.path);
+            exit(1);
+        }
+        if t.confirm.is_some() {
+            println!(
+                "{}: export does not work with CONFIRM or :danger: jobs, run it with orgjob to confirm it",
+                t.path
+            );
+            exit(1);
+        }
+    }
+    if let Some(n) = order.last() {
+        if job_lock(doc, *n, "", false).is_some() {
+            println!(
+                "{}: export does not work with LOCK, orgjob takes the lock when it runs the job",
+                doc.get_fullname(*n).join(settings.sep)
+            );
+            exit(1);
+        }
+    }
+    for n in order.iter() {
+        if let Some(path) = env_file_paths(doc, *n, settings).first() {
+            println!(
+                "{}: export does not work with ENV_FILE, {} is read when the job runs",
+                doc.get_fullname(*n).join(settings.sep),
+                path.display()
+            );
+            exit(1);
+        }
+    }
+}
+
+///names of the environment variables listed in SECRETS
+fn job_secrets(doc: &CodeDoc, node: DocNodeId) -> Vec<String> {
+    return match doc.get_inherited_property(node, "SECRETS") {
+        Some(value) => value.split_whitespace().map(|n| n.to_string()).collect(),
+        None => Vec::new(),
+    };
+}
+
+///MAX_MEMORY, MAX_CPU_SECONDS, MAX_FILE_SIZE and NICE properties, each taken
+///from the node or its closest ancestor that has it
+fn job_limits(doc: &CodeDoc, node: DocNodeId) -> Limits {
+    let invalid = |key: &str, value: &str| -> ! {
+        println!(
+            "invalid {} for {}: {}",
+            key,
+            doc.get_fullname(node).join("."),
+            value
+        );
+        exit(1);
+    };
+    let property = |key: &str| doc.get_inherited_property(node, key);
+    let size = |key: &str| {
+        let value = property(key)?;
+        return Some(parse_size(value).unwrap_or_else(|| invalid(key, value)));
+    };
+    let cpu_seconds = property("MAX_CPU_SECONDS").map(|value| match parse_duration(value) {
+        Some(d) if d.as_secs_f64() >= 1.0 => d.as_secs_f64().ceil() as u64,
+        _ => invalid("MAX_CPU_SECONDS", value),
+    });
+    let nice = property("NICE").map(|value| match value.parse::<i32>() {
+        Ok(n) if (-20..=19).contains(&n) => n,
+        _ => invalid("NICE", value),
+    });
+    return Limits {
+        memory: size("MAX_MEMORY"),
+        cpu_seconds,
+        file_size: size("MAX_FILE_SIZE"),
+        nice,
+    };
+}
+
+///bind_params_with asking for missing values, which a dry run leaves unbound
+fn bind_or_ask(
+    params: &[Param],
+    args: &[String],
+    settings: &RunSettings,
+) -> std::result::Result<BoundParams, ParamError> {
+    let mut unbound = Vec::new();
+    let mut bound = bind_params_with(params, args, |p| {
+        if settings.dry_run {
+            unbound.push(p.name.clone());
+            return Ok(String::new());
+        }
+        return prompt_param(p);
+    })?;
+    bound.values.retain(|(k, _)| !unbound.contains(k));
+    bound.unbound = unbound;
+    return Ok(bound);
+}
+
+///match the job arguments against the declared parameters, printing the
+///job's help or the error and exiting when there is nothing to run
+fn bind_job_params(code: &RunnableCode, args: &[String], settings: &RunSettings) -> BoundParams {
+    match bind_or_ask(&code.params, args, settings) {
+        Ok(bound) => {
+            if bound.help {
+                print!(
+                    "{}",
+                    params_help(&code.fullname.join(settings.sep), &code.params)
+                );
+                exit(0);
+            }
+            return bound;
+        }
+        Err(e) => {
+            println!("{}", e);
+            exit(1);
+        }
+    }
+}
+
+///retry policy from the RETRIES, RETRY_DELAY, RETRY_BACKOFF and RETRY_ON
+///properties, each taken from the node or its closest ancestor that has it
+fn job_retry(doc: &CodeDoc, node: DocNodeId) -> Option<RetryPolicy> {
+    let retries = doc.get_inherited_property(node, "RETRIES")?;
+    let invalid = |key: &str, value: &str| -> ! {
+        println!(
+            "invalid {} for {}: {}",
+            key,
+            doc.get_fullname(node).join("."),
+            value
+        );
+        exit(1);
+    };
+    let mut policy = RetryPolicy {
+        retries: retries
+            .parse()
+            .unwrap_or_else(|_| invalid("RETRIES", retries)),
+        delay: Duration::from_secs(1),
+        backoff: 1.0,
+        exit_codes: Vec::new(),
+    };
+    if let Some(v) = doc.get_inherited_property(node, "RETRY_DELAY") {
+        policy.delay = parse_duration(v).unwrap_or_else(|| invalid("RETRY_DELAY", v));
+    }
+    if let Some(v) = doc.get_inherited_property(node, "RETRY_BACKOFF") {
+        policy.backoff = match v.parse::<f64>() {
+            Ok(b) if b >= 1.0 => b,
+            _ => invalid("RETRY_BACKOFF", v),
+        };
+    }
+    if let Some(v) = doc.get_inherited_property(node, "RETRY_ON") {
+        for code in v.split_whitespace() {
+            policy
+                .exit_codes
+                .push(code.parse().unwrap_or_else(|_| invalid("RETRY_ON", v)));
+        }
+    }
+    if policy.retries == 0 {
+        return None;
+    }
+    return Some(policy);
+}
+
+///the code block of a node that starts on line linum
+fn origin_block(doc: &CodeDoc, node: DocNodeId, linum: usize) -> &CodeBlock {
+    return doc
+        .get_node(node)
+        .code_blocks()
+        .iter()
+        .find(|cb| cb.linum == linum)
+        .unwrap();
+}
+
+///the `:session` of a job: the closest one on a block in its language of the
+///node or its ancestors, or else the first one below it. A session without a
+///name is `default`, `:session none` turns it off.
+fn job_session(doc: &CodeDoc, node: DocNodeId, c: &RunnableCode) -> Option<String> {
+    let mut n = node;
+    let mut session = None;
+    while n != DOC_NODE_ROOT_ID && session.is_none() {
+        session = doc
+            .get_node(n)
+            .code_blocks()
+            .iter()
+            .filter(|cb| cb.interpreter == c.interpreter)
+            .find_map(|cb| cb.header_arg("session"));
+        n = doc.get_parent(n).unwrap();
+    }
+    let session = session.or_else(|| {
+        c.origin
+            .iter()
+            .find_map(|(n, linum)| origin_block(doc, *n, *linum).header_arg("session"))
+    })?;
+    match session {
+        "none" => return None,
+        "" => return Some("default".to_string()),
+        name => return Some(name.to_string()),
+    }
+}
+
+///what has to be typed before the node runs. The closest heading with a
+///CONFIRM property or a :danger: tag decides: the CONFIRM value, or the name
+///of that heading when the value is `t` or `yes` or there is only the tag.
+///`CONFIRM: no` turns the gate off below a dangerous heading.
+fn job_confirm(doc: &CodeDoc, node: DocNodeId) -> Option<String> {
+    let mut n = node;
+    while n != DOC_NODE_ROOT_ID {
+        let heading = doc.get_node(n);
+        match heading.property("CONFIRM") {
+            Some(v) if v.eq_ignore_ascii_case("no") || v.eq_ignore_ascii_case("nil") => {
+                return None
+            }
+            Some(v)
+                if v.is_empty() || v.eq_ignore_ascii_case("t") || v.eq_ignore_ascii_case("yes") =>
+            {
+                return Some(heading.name().to_string())
+            }
+            Some(v) => return Some(v.to_string()),
+            None => {}
+        }
+        if heading.has_tag("danger") {
+            return Some(heading.name().to_string());
+        }
+        n = doc.get_parent(n)?;
+    }
+    return None;
+}
+
+///the `:stdin` or `:input` of a task: that of its block in a pipeline, or else the
+///closest one on a block in its language of the node or its ancestors
+fn job_stdin<'a>(
+    doc: &'a CodeDoc,
+    node: DocNodeId,
+    interpreter: &str,
+    block: Option<&'a CodeBlock>,
+) -> Option<&'a str> {
+    if let Some(cb) = block {
+        return cb.stdin();
+    }
+    let mut n = node;
+    while n != DOC_NODE_ROOT_ID {
+        let found = doc
+            .get_node(n)
+            .code_blocks()
+            .iter()
+            .filter(|cb| cb.interpreter == interpreter)
+            .find_map(|cb| cb.stdin());
+        if found.is_some() {
+            return found;
+        }
+        n = doc.get_parent(n).unwrap();
+    }
+    return None;
+}
+
+///what a `:stdin` or --stdin-from value names: an example block with a
+///`#+NAME:`, `job:PATH` for the output of a job that runs before, or else a
+///file, relative to the org file's directory when the value is in the doc
+fn resolve_stdin(
+    doc: &CodeDoc,
+    value: &str,
+    in_doc: bool,
+    task_nodes: &[DocNodeId],
+    settings: &RunSettings,
+) -> TaskInput {
+    if let Some(text) = doc.example(value) {
+        return TaskInput::Example {
+            name: value.to_string(),
+            text: text.to_string(),
+        };
+    }
+    if let Some(path) = value.strip_prefix("job:") {
+        let nodes = find_jobs(doc, path, settings.sep);
+        let task = match nodes.as_slice() {
+            [node] => task_nodes.iter().position(|n| n == node),
+            _ => None,
+        };
+        match task {
+            Some(j) => return TaskInput::Output(j),
+            None => {
+                println!("no job that runs before to read stdin from: {}", path);
+                exit(1);
+            }
+        }
+    }
+    if in_doc {
+        return TaskInput::File(settings.dir_base.join(value));
+    }
+    return TaskInput::File(PathBuf::from(value));
+}
+
+///names of the blocks of a pipeline, and the parameters of the pipeline:
+///those of every language, except `:var x=NAME` naming one of its blocks
+fn pipeline_code(doc: &CodeDoc, job: DocNodeId, sep: &str) -> (Vec<String>, RunnableCode) {
+    let steps = doc.get_pipeline(job);
+    let names: Vec<String> = steps
+        .iter()
+        .filter_map(|(n, linum)| origin_block(doc, *n, *linum).name.clone())
+        .collect();
+    let mut params: Vec<Param> = Vec::new();
+    for c in doc.get_runnable_code(job, sep) {
+        for p in c.params {
+            let is_output = p.default.as_ref().is_some_and(|d| names.contains(d));
+            if !is_output && !params.iter().any(|x| x.name == p.name) {
+                params.push(p);
+            }
+        }
+    }
+    let code = RunnableCode {
+        interpreter: String::new(),
+        fullname: doc.get_fullname(job),
+        code: Vec::new(),
+        creates: Vec::new(),
+        sources: Vec::new(),
+        params,
+        origin: steps,
+    };
+    return (names, code);
+}
+
+///parameters of a job that runs as a dependency, none when it has no code and
+///so no task; it gets the values of the job's parameters of the same name
+fn dependency_params(
+    doc: &CodeDoc,
+    node: DocNodeId,
+    job: &BoundParams,
+    settings: &RunSettings,
+) -> Option<BoundParams> {
+    let code = doc.get_runnable_code(node, settings.sep);
+    if code.is_empty() {
+        return None;
+    }
+    let c = select_code(doc, node, &code, &doc.get_fullname(node), settings.lang);
+    let args: Vec<String> = job
+        .values
+        .iter()
+        .filter(|(k, _)| c.params.iter().any(|p| p.name == *k))
+        .map(|(k, v)| format!("--{}={}", k, v))
+        .collect();
+    match bind_or_ask(&c.params, &args, settings) {
+        Ok(bound) => return Some(bound),
+        Err(e) => {
+            println!("{}: {}", doc.get_fullname(node).join(settings.sep), e);
+            exit(1);
+        }
+    }
+}
+
+///the tasks a node depends on, through those of dependencies that have no task
+fn task_deps(doc: &CodeDoc, node: DocNodeId, sep: &str, task_nodes: &[DocNodeId]) -> Vec<usize> {
+    let mut deps = Vec::new();
+    for d in doc.get_dependencies(node, sep).unwrap() {
+        let found = match task_nodes.iter().position(|n| *n == d) {
+            Some(i) => vec![i],
+            None => task_deps(doc, d, sep, task_nodes),
+        };
+        for i in found {
+            if !deps.contains(&i) {
+                deps.push(i);
+            }
+        }
+    }
+    return deps;
+}
+
+///one task per node of the dependency order; in parallel runs the job itself
+///is split into one task per child heading so that siblings can run side by side,
+///in a pipeline into one task per code block.
+///Without job arguments the job's parameters are left for the caller to bind.
+fn build_tasks(
+    doc: &CodeDoc,
+    order: &[DocNodeId],
+    args: Option<&[String]>,
+    settings: &RunSettings,
+) -> Vec<Task> {
+    let sep = settings.sep;
+    let mut tasks = Vec::new();
+    let mut task_nodes = Vec::new();
+    let (job, deps) = order.split_last().unwrap();
+    //a block given, the task runs only that block of the node, in its language
+    let mut add_task = |tasks: &mut Vec<Task>,
+                        node: DocNodeId,
+                        dep_of: &[DocNodeId],
+                        after: Option<usize>,
+                        bound: &BoundParams,
+                        block: Option<&CodeBlock>| {
+        let code = doc.get_runnable_code(node, sep);
+        let c = match block {
+            Some(cb) => code
+                .iter()
+                .find(|c| c.interpreter == cb.interpreter)
+                .unwrap(),
+            None => select_code(doc, node, &code, &doc.get_fullname(node), settings.lang),
+        };
+        let mut deps = Vec::new();
+        for d in dep_of
+            .iter()
+            .flat_map(|n| task_deps(doc, *n, sep, &task_nodes))
+        {
+            if !deps.contains(&d) {
+                deps.push(d);
+            }
+        }
+        deps.extend(after);
+        let names: Vec<&str> = match args {
+            Some(_) => bound.values.iter().map(|(k, _)| k.as_str()).collect(),
+            None => c.params.iter().map(|p| p.name.as_str()).collect(),
+        };
+        let mut hashed: Vec<String> = bound
+            .values
+            .iter()
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect();
+        hashed.extend(bound.positional.iter().cloned());
+        let dir = job_dir(doc, node, &c.interpreter, settings);
+        //--stdin-from is for the first task of the job, in place of its :stdin
+        let stdin = match settings
+            .stdin_from
+            .filter(|_| dep_of.contains(job) && after.is_none())
+        {
+            Some(value) => Some(resolve_stdin(doc, value, false, &task_nodes, settings)),
+            None => job_stdin(doc, node, &c.interpreter, block)
+                .map(|value| resolve_stdin(doc, value, true, &task_nodes, settings)),
+        };
+        if let Some(TaskInput::Output(j)) = &stdin {
+            if !deps.contains(j) {
+                deps.push(*j);
+            }
+        }
+        //later sources win: env files, --env, parameters, then orgjob's own variables
+        let file_env = match settings.run_id {
+            Some(_) => job_env_files(doc, node, settings),
+            None => Vec::new(),
+        };
+        let mut env = Vec::new();
+        let path = doc.get_fullname(node).join(sep);
+        let mut own = org_env(settings.org_file);
+        own.push(("ORGJOB_JOB".to_string(), path.clone()));
+        if let Some(id) = settings.run_id {
+            own.push(("ORGJOB_RUN_ID".to_string(), id.to_string()));
+        }
+        for (k, v) in file_env
+            .iter()
+            .chain(settings.env.iter())
+            .chain(bound.values.iter())
+            .chain(own.iter())
+        {
+            set_env(&mut env, k, v);
+        }
+        //values from env files are treated as secrets and kept out of logs
+        let mut secrets: Vec<String> = c
+            .params
+            .iter()
+            .filter(|p| p.secret)
+            .map(|p| p.name.clone())
+            .collect();
+        secrets.extend(file_env.into_iter().map(|(k, _)| k));
+        //values of secret parameters and SECRETS are masked in the output,
+        //those of SECRETS found in orgjob's environment when the task has none
+        let mut mask: Vec<String> = bound
+            .values
+            .iter()
+            .filter(|(k, _)| c.params.iter().any(|p| p.secret && p.name == *k))
+            .map(|(_, v)| v.clone())
+            .collect();
+        for name in job_secrets(doc, node) {
+            let value = match env.iter().find(|(k, _)| *k == name) {
+                Some((_, v)) => Some(v.clone()),
+                None => std::env::var(&name).ok(),
+            };
+            mask.extend(value);
+            if !secrets.contains(&name) {
+                secrets.push(name);
+            }
+        }
+        let prelude = param_prelude(&c.interpreter, &names);
+        let session = job_session(doc, node, c).filter(|_| block.is_none());
+        if session.is_some() && stdin.is_some() {
+            println!(
+                "{}: :stdin does not work with :session blocks, the session reads its code from stdin",
+                path
+            );
+            exit(1);
+        }
+        let mut blocks = Vec::new();
+        if session.is_some() {
+            if session_style(&c.interpreter).is_none() {
+                println!("{}: {} does not support :session", path, c.interpreter);
+                exit(1);
+            }
+            //the prelude has no id, so that it runs for every task
+            blocks.push((String::new(), prelude.clone()));
+            for (n, linum) in c.origin.iter() {
+                let cb = origin_block(doc, *n, *linum);
+                blocks.push((linum.to_string(), cb.code.clone()));
+            }
+        }
+        let (name, origin, code) = match block {
+            Some(cb) => {
+                let n = c.origin.iter().find(|(_, l)| *l == cb.linum).unwrap().0;
+                (
+                    cb.name
+                        .clone()
+                        .unwrap_or_else(|| doc.get_node(n).name().to_string()),
+                    vec![(doc.get_fullname(n).join(sep), cb.linum)],
+                    cb.code.clone(),
+                )
+            }
+            None => (
+                doc.get_node(node).name().to_string(),
+                c.origin
+                    .iter()
+                    .map(|(n, linum)| (doc.get_fullname(*n).join(sep), *linum))
+                    .collect(),
+                c.code.join("\n"),
+            ),
+        };
+        tasks.push(Task {
+            name,
+            path,
+            origin,
+            interpreter: c.interpreter.clone(),
+            code: prelude + &code,
+            args: bound.positional.clone(),
+            env,
+            secrets,
+            mask,
+            deps,
+            check: match block {
+                Some(_) => None,
+                None => build_check(c, &hashed, &dir, settings),
+            },
+            dir: Some(dir),
+            timeout: settings.timeout.or_else(|| job_timeout(doc, node)),
+            retry: job_retry(doc, node),
+            confirm: job_confirm(doc, node),
+            session,
+            blocks,
+            stdin,
+            inputs: Vec::new(),
+            limits: job_limits(doc, node),
+            unbound: bound.unbound.clone(),
+        });
+        task_nodes.push(node);
+    };
+    let bound = match args {
+        Some(args) if settings.pipeline => {
+            bind_job_params(&pipeline_code(doc, *job, sep).1, args, settings)
+        }
+        Some(args) => {
+            let code = doc.get_runnable_code(*job, sep);
+            let c = select_code(doc, *job, &code, &doc.get_fullname(*job), settings.lang);
+            bind_job_params(c, args, settings)
+        }
+        None => BoundParams::default(),
+    };
+    for dep in deps {
+        if let Some(dep_bound) = dependency_params(doc, *dep, &bound, settings) {
+            add_task(&mut tasks, *dep, &[*dep], None, &dep_bound, None);
+        }
+    }
+    if settings.pipeline {
+        let (names, code) = pipeline_code(doc, *job, sep);
+        let mut named: Vec<(&str, usize)> = Vec::new();
+        for (k, (n, linum)) in code.origin.iter().enumerate() {
+            let cb = origin_block(doc, *n, *linum);
+            let after = if k == 0 { None } else { Some(tasks.len() - 1) };
+            add_task(&mut tasks, *job, &[*job], after, &bound, Some(cb));
+            let index = tasks.len() - 1;
+            let t = tasks.last_mut().unwrap();
+            t.path = t.origin[0].0.clone();
+            //`:stdin NAME` may name an earlier step, by default a step reads the last one
+            let step = cb.stdin().and_then(|v| named.iter().find(|(x, _)| *x == v));
+            if let Some((_, j)) = step {
+                t.stdin = Some(TaskInput::Output(*j));
+            } else if t.stdin.is_none() {
+                t.stdin = after.map(TaskInput::Output);
+            }
+            for (key, value) in cb.header_args.iter() {
+                if !key.eq_ignore_ascii_case("var") {
+                    continue;
+                }
+                for p in parse_params(value) {
+                    let from = p.default.as_ref().filter(|d| names.contains(d));
+                    if let Some(&(_, j)) = from.and_then(|d| named.iter().find(|(x, _)| x == d)) {
+                        t.inputs.push((p.name, j));
+                    }
+                }
+            }
+            let inputs: Vec<&str> = t.inputs.iter().map(|(k, _)| k.as_str()).collect();
+            t.code = param_prelude(&t.interpreter, &inputs) + &t.code;
+            if let Some(name) = &cb.name {
+                named.push((name, index));
+            }
+        }
+        return tasks;
+    }
+    let children = doc.get_children(*job);
+    //with retries the children run as separate steps, one after the other,
+    //so that only the failing one runs again; in a session each step only
+    //runs its own blocks, those of the ancestors run once
+    let stepwise = args.is_some()
+        && children.iter().any(|child| {
+            let code = doc.get_runnable_code(*child, sep);
+            return job_retry(doc, *child).is_some()
+                || find_code(&code, settings.lang)
+                    .is_some_and(|c| job_session(doc, *child, c).is_some());
+        });
+    if children.is_empty() || !(settings.parallel || stepwise) {
+        add_task(&mut tasks, *job, &[*job], None, &bound, None);
+    } else {
+        //a child runs after what its own DEPENDS names, siblings or other jobs
+        let mut steps: Vec<DocNodeId> = Vec::new();
+        for child in children.iter() {
+            let order = match doc.get_dependency_order(*child, sep) {
+                Ok(order) => order,
+                Err(e) => {
+                    print_dependency_error(doc, &e, sep);
+                    exit(1);
+                }
+            };
+            for n in order {
+                if n == *job {
+                    println!(
+                        "{} cannot depend on {}, which it is part of",
+                        doc.get_fullname(*child).join(sep),
+                        doc.get_fullname(*job).join(sep)
+                    );
+                    exit(1);
+                }
+                if !deps.contains(&n) && !steps.contains(&n) {
+                    steps.push(n);
+                }
+            }
+        }
+        let mut first = true;
+        for n in steps {
+            if !children.contains(&n) {
+                if let Some(dep_bound) = dependency_params(doc, n, &bound, settings) {
+                    add_task(&mut tasks, n, &[n], None, &dep_bound, None);
+                }
+                continue;
+            }
+            let after = if settings.parallel || first {
+                None
+            } else {
+                Some(tasks.len() - 1)
+            };
+            first = false;
+            add_task(&mut tasks, n, &[*job, n], after, &bound, None);
+        }
+    }
+    return tasks;
+}
+
+fn tangle(doc: &CodeDoc, nodes: &[DocNodeId], org_file: &Path) {
+    let files = match collect_tangled_files(doc, nodes, org_file) {
+        Ok(files) => files,
+        Err(TangleError::InvalidMode { target, value }) => {
+            println!("invalid tangle-mode for {}: {}", target, value);
+            exit(1);
+        }
+    };
+    for f in files.iter() {
+        match write_tangled_file(f) {
+            Ok(true) => println!("changed: {}", f.path.display()),
+            Ok(false) => println!("unchanged: {}", f.path.display()),
+            Err(e) => {
+                println!("failed to write {}: {}", f.path.display(), e);
+                exit(1);
+            }
+        }
+    }
+}
+
+///start the record of a run under the state directory, or run without one
+fn open_run_log(org_file: &Path, run_id: &str, job: &str) -> Option<RunLog> {
+    let state = match state_dir() {
+        Some(d) => d,
+        None => {
+            println!("neither XDG_STATE_HOME nor HOME is set, the run is not logged");
+            return None;
+        }
+    };
+    let job_dir = job_log_dir(&doc_log_dir(&state, org_file), job);
+    match RunLog::create(&job_dir, run_id, job, org_file) {
+        Ok(log) => return Some(log),
+        Err(e) => {
+            println!("failed to create run log in {}: {}", job_dir.display(), e);
+            return None;
+        }
+    }
+}
+
+///name of the lock a run of the job takes: the value of the closest LOCK
+///property, or the job itself for `t` or `yes` and with --lock
+fn job_lock(doc: &CodeDoc, node: DocNodeId, job: &str, forced: bool) -> Option<String> {
+    match doc.get_inherited_property(node, "LOCK") {
+        Some(v) if v.eq_ignore_ascii_case("no") || v.eq_ignore_ascii_case("nil") => return None,
+        Some(v) if v.is_empty() || v.eq_ignore_ascii_case("t") || v.eq_ignore_ascii_case("yes") => {
+            return Some(job.to_string())
+        }
+        Some(v) => return Some(v.to_string()),
+        None if forced => return Some(job.to_string()),
+        None => return None,
+    }
+}
+
+fn job_lock_path(org_file: &Path, key: &str) -> PathBuf {
+    match state_dir() {
+        Some(state) => return lock_path(&state, org_file, key),
+        None => {
+            println!(
+                "neither XDG_STATE_HOME nor HOME is set, cannot lock {}",
+                key
+            );
+            exit(1);
+        }
+    }
+}
+
+///take the lock of a run, failing when somebody holds it unless told to wait
+fn take_lock(org_file: &Path, key: &str, wait: bool) -> JobLock {
+    let path = job_lock_path(org_file, key);
+    let mut result = JobLock::acquire(&path, false);
+    if let (Err(LockError::Held(holder)), true) = (&result, wait) {
+        match holder {
+            Some(h) => println!("waiting for the lock of {}, held by {}", key, h),
+            None => println!("waiting for the lock of {}", key),
+        }
+        result = JobLock::acquire(&path, true);
+    }
+    match result {
+        Ok(lock) => {
+            if let Some(h) = &lock.stale {
+                println!(
+                    "took over the stale lock of {} from {}, which is gone",
+                    key, h
+                );
+            }
+            return lock;
+        }
+        Err(LockError::Held(holder)) => {
+            match holder {
+                Some(h) => println!("{} is locked by {}", key, h),
+                None => println!("{} is locked", key),
+            }
+            println!("use --wait to wait for it");
+            exit(1);
+        }
+        Err(LockError::Io(e)) => {
+            println!("failed to lock {} at {}: {}", key, path.display(), e);
+            exit(1);
+        }
+    }
+}
+
+fn print_history(org_file: &Path, job: Option<&str>) {
+    let doc_dir = match state_dir() {
+        Some(state) => doc_log_dir(&state, org_file),
+        None => return,
+    };
+    let job_dir = job.map(|j| job_log_dir(&doc_dir, j));
+    for run in list_runs(&doc_dir, job_dir.as_deref()) {
+        let status = match run.status {
+            Some(0) => "ok".to_string(),
+            Some(code) => format!("failed ({})", code),
+            None => "unfinished".to_string(),
+        };
+        println!(
+            "{}  {}  {}  {}",
+            run.started,
+            run.job,
+            status,
+            run.duration.as_deref().unwrap_or("-")
+        );
+    }
+}
+
+fn print_logs(org_file: &Path, job: &str, last: bool) {
+    let mut runs = match state_dir() {
+        Some(state) => {
+            let doc_dir = doc_log_dir(&state, org_file);
+            list_runs(&doc_dir, Some(&job_log_dir(&doc_dir, job)))
+        }
+        None => Vec::new(),
+    };
+    if runs.is_empty() {
+        println!("no recorded runs of {}", job);
+        exit(1);
+    }
+    if last {
+        runs.drain(..runs.len() - 1);
+    }
+    for (i, run) in runs.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        println!("run {}", run.dir.display());
+        match read_run_log(run) {
+            Ok(text) => print!("{}", text),
+            Err(e) => println!("failed to read {}: {}", run.dir.display(), e),
+        }
+    }
+}
+
+///describe what run would do, without starting anything
+fn dry_run_plan(job: &str, org_file: &Path, cwd: &Path, tasks: &[Task]) -> String {
+    let mut out = format!("dry run of {}, nothing is started\n", job);
+    for (i, t) in tasks.iter().enumerate() {
+        out.push_str(&format!("\n{}. {}\n", i + 1, t.path));
+        let dir = t.dir.as_deref().unwrap_or(cwd);
+        out.push_str(&format!("   working directory: {}\n", dir.display()));
+        let args: Vec<String> = t.args.iter().map(|a| quote(a)).collect();
+        out.push_str(&format!("   command: {} <script>\n", t.interpreter));
+        if let Some(session) = &t.session {
+            out.push_str(&format!("   session: {}\n", session));
+        }
+        match &t.stdin {
+            Some(TaskInput::Output(j)) => {
+                out.push_str(&format!("   stdin: output of {}\n", tasks[*j].name))
+            }
+            Some(TaskInput::File(path)) => {
+                out.push_str(&format!("   stdin: file {}\n", path.display()))
+            }
+            Some(TaskInput::Example { name, .. }) => {
+                out.push_str(&format!("   stdin: example {}\n", name))
+            }
+            None => {}
+        }
+        for (name, j) in t.inputs.iter() {
+            out.push_str(&format!(
+                "   input: {} = output of {}\n",
+                name, tasks[*j].name
+            ));
+        }
+        if args.is_empty() {
+            out.push_str("   arguments: none\n");
+        } else {
+            out.push_str(&format!("   arguments: {}\n", args.join(" ")));
+        }
+        if !t.unbound.is_empty() {
+            out.push_str(&format!(
+                "   unbound parameters: {}\n",
+                t.unbound.join(", ")
+            ));
+        }
+        if t.env.is_empty() {
+            out.push_str("   environment: none\n");
+        } else {
+            out.push_str("   environment:\n");
+            for (k, v) in t.env.iter() {
+                if t.secrets.contains(k) {
+                    out.push_str(&format!("     {}={}\n", k, MASK));
+                } else {
+                    out.push_str(&format!("     {}={}\n", k, quote(v)));
+                }
+            }
+        }
+        if t.deps.is_empty() {
+            out.push_str("   depends on: nothing\n");
+        } else {
+            let deps: Vec<&str> = t.deps.iter().map(|d| tasks[*d].path.as_str()).collect();
+            out.push_str(&format!("   depends on: {}\n", deps.join(", ")));
+        }
+        if let Some(check) = &t.check {
+            match check.check() {
+                Freshness::UpToDate => out.push_str("   up to date, would be skipped\n"),
+                Freshness::Stale(why) => out.push_str(&format!("   would run, {}\n", why)),
+            }
+        }
+        if let Some(timeout) = t.timeout {
+            out.push_str(&format!("   timeout: {:?}\n", timeout));
+        }
+        if let Some(retry) = &t.retry {
+            out.push_str(&format!("   retries: {}\n", retry.retries));
+        }
+        if !t.limits.is_empty() {
+            let mut limits = Vec::new();
+            if let Some(bytes) = t.limits.memory {
+                limits.push(format!("memory {} bytes", bytes));
+            }
+            if let Some(secs) = t.limits.cpu_seconds {
+                limits.push(format!("cpu {}s", secs));
+            }
+            if let Some(bytes) = t.limits.file_size {
+                limits.push(format!("file size {} bytes", bytes));
+            }
+            if let Some(n) = t.limits.nice {
+                limits.push(format!("nice {}", n));
+            }
+            out.push_str(&format!("   limits: {}\n", limits.join(", ")));
+        }
+        if let Some(phrase) = &t.confirm {
+            out.push_str(&format!("   needs confirmation: type {}\n", phrase));
+        }
+        out.push_str("   code:\n");
+        for (path, linum) in t.origin.iter() {
+            let role = if path == &t.path {
+                ""
+            } else if t.path.starts_with(path.as_str()) {
+                " (ancestor)"
+            } else {
+                " (descendant)"
+            };
+            out.push_str(&format!(
+                "     {}:{} {}{}\n",
+                org_file.display(),
+                linum,
+                path,
+                role
+            ));
+        }
+    }
+    return out;
+}
+
+///show what needs confirmation and ask for it; without a terminal only --yes lets the run go on
+fn confirm_run(job: &str, tasks: &[Task], yes: bool) {
+    if yes || tasks.iter().all(|t| t.confirm.is_none()) {
+        return;
+    }
+    println!("{} would run:", job);
+    for t in tasks.iter() {
+        match &t.confirm {
+            Some(_) => println!("  {}  (needs confirmation)", t.path),
+            None => println!("  {}", t.path),
+        }
+    }
+    if !stdin_is_tty() {
+        println!("no terminal to confirm on, use --yes to run anyway");
+        exit(1);
+    }
+    let mut phrases: Vec<&str> = Vec::new();
+    for t in tasks.iter() {
+        if let Some(p) = &t.confirm {
+            if !phrases.contains(&p.as_str()) {
+                phrases.push(p);
+            }
+        }
+    }
+    for p in phrases {
+        if !confirm(p) {
+            println!("not confirmed, nothing was run");
+            exit(1);
+        }
+    }
+}
+
+fn write_report(
+    path: &Path,
+    format: ReportFormat,
+    job: &str,
+    tasks: &[Task],
+    runs: &[TaskRun],
+    log: &RunLog,
+    duration: Duration,
+) -> Result<()> {
+    let cases: Vec<ReportCase> = tasks
+        .iter()
+        .zip(runs.iter())
+        .enumerate()
+        .map(|(i, (t, r))| {
+            let (stdout, stderr) = match r.attempts {
+                0 => (String::new(), String::new()),
+                n => log.read_output(i, t, n),
+            };
+            return ReportCase {
+                name: t.path.clone(),
+                interpreter: t.interpreter.clone(),
+                run: r.clone(),
+                stdout,
+                stderr,
+            };
+        })
+        .collect();
+    let status: Vec<TaskStatus> = runs.iter().map(|r| r.status.clone()).collect();
+    let report = match format {
+        ReportFormat::Json => json_report(job, aggregate_status(&status), duration, &cases),
+        ReportFormat::Junit => junit_report(job, duration, &cases),
+    };
+    return std::fs::write(path, report);
+}
+
+fn write_script(path: &Path, script: &str) -> Result<()> {
+    std::fs::write(path, script)?;
+    let mut perms = std::fs::metadata(path)?.permissions();
+    perms.set_mode(0o755);
+    return std::fs::set_permissions(path, perms);
+}
+
+///orgjob options come before the job or among its arguments, which start
+///after `--` when one of them starts with -
+fn cli_app() -> App<'static, 'static> {
+    return App::new("Run code in org doc by hierarchy")
         .version("1.0")
+        .arg(
+            Arg::with_name("help")
+                .short("h")
+                .long("help")
+                .help("print help, or the parameters of the job"),
+        )
         .arg(
             Arg::with_name("org_file")
                 .short("f")
@@ -51,31 +1456,221 @@ fn main() -> Result<()> {
                 .help("specify script language in case of ambiguity")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("jobs")
+                .short("j")
+                .long("jobs")
+                .value_name("N")
+                .help("run independent jobs and sibling child jobs in parallel, N at a time")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("fail_fast")
+                .long("fail-fast")
+                .help("with -j, stop all jobs as soon as one fails"),
+        )
+        .arg(
+            Arg::with_name("force")
+                .long("force")
+                .help("run jobs even when their CREATES outputs are up to date"),
+        )
+        .arg(
+            Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("FILE")
+                .help("file to write an exported script to")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("timeout")
+                .long("timeout")
+                .value_name("DURATION")
+                .help("stop each job that runs longer, e.g. 90, 30s, 5m; overrides TIMEOUT properties")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("no_log")
+                .long("no-log")
+                .help("do not record the run under $XDG_STATE_HOME/orgjob"),
+        )
+        .arg(
+            Arg::with_name("last")
+                .long("last")
+                .help("with log, show only the most recent run"),
+        )
+        .arg(
+            Arg::with_name("env")
+                .short("e")
+                .long("env")
+                .value_name("KEY=VALUE")
+                .multiple(true)
+                .number_of_values(1)
+                .help("set an environment variable for the jobs, overriding ENV_FILE"),
+        )
+        .arg(
+            Arg::with_name("clean_env")
+                .long("clean-env")
+                .help("pass only PATH, HOME, USER, SHELL, TERM, locale and TZ from orgjob's environment"),
+        )
+        .arg(
+            Arg::with_name("stdin_from")
+                .long("stdin-from")
+                .value_name("SOURCE")
+                .help("feed the job a file, a named example block or job:PATH, the output of another job")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("pipeline")
+                .long("pipeline")
+                .help("run every block of the job in document order, whatever its language, each reading the output of the one before"),
+        )
+        .arg(
+            Arg::with_name("from_cwd")
+                .long("from-cwd")
+                .help("run jobs in the current directory instead of the org file's, and resolve relative DIR properties and :dir header args against it"),
+        )
+        .arg(
+            Arg::with_name("watch")
+                .long("watch")
+                .help("run the job again whenever the org file, its env files or the job's SOURCES change"),
+        )
+        .arg(
+            Arg::with_name("logbook")
+                .long("logbook")
+                .help("with daemon, note every run in the LOGBOOK drawer of its heading"),
+        )
+        .arg(
+            Arg::with_name("keep_script")
+                .long("keep-script")
+                .value_name("DIR")
+                .min_values(0)
+                .require_equals(true)
+                .help("keep the script of every task, in DIR or a new temp directory, and say where"),
+        )
+        .arg(
+            Arg::with_name("trace")
+                .long("trace")
+                .help("run the interpreters in their trace mode, like bash -x"),
+        )
+        .arg(
+            Arg::with_name("terminal")
+                .long("terminal")
+                .help("give a job that runs alone the terminal instead of logging its output"),
+        )
+        .arg(
+            Arg::with_name("lock")
+                .long("lock")
+                .help("do not run the job while another run of it holds its lock"),
+        )
+        .arg(
+            Arg::with_name("wait")
+                .long("wait")
+                .help("wait for the lock of a job instead of failing"),
+        )
+        .arg(
+            Arg::with_name("yes")
+                .short("y")
+                .long("yes")
+                .help("run jobs that need confirmation without asking"),
+        )
+        .arg(
+            Arg::with_name("dry_run")
+                .long("dry-run")
+                .help("show what run would do without running anything"),
+        )
+        .arg(
+            Arg::with_name("report")
+                .long("report")
+                .value_names(&["FORMAT", "PATH"])
+                .number_of_values(2)
+                .help("write a json or junit report of the run to PATH"),
+        )
         .arg(
             Arg::with_name("action")
                 .help("action to do with the job")
                 .index(1)
                 .required(true)
                 .default_value("list")
-                .possible_values(&["run", "list", "show"]),
+                .possible_values(&[
+                    "run", "list", "show", "graph", "tangle", "export", "history", "log",
+                    "daemon",
+                ]),
         )
         .arg(
             Arg::with_name("job")
-                .help("job to run")
+                .help("job to run, a path of heading names like deploy.prod; segments can be =exact, prefix*, globs, where \\ makes a wildcard plain, as are cookies like [1/3], or /regex/, and a leading ^ anchors the path at the top")
                 .required(false)
                 .index(2),
         )
         .arg(
             Arg::with_name("args")
-                .help("arguments for the job")
+                .help("arguments for the job, after -- when one starts with -")
                 .required(false)
                 .multiple(true)
                 .index(3),
-        )
-        .get_matches();
+        );
+}
+
+fn main() -> Result<()> {
+    let mut app = cli_app();
+    let matches = app.clone().get_matches();
+    if matches.is_present("help") && !matches.is_present("job") {
+        if app.print_help().is_ok() {
+            println!();
+        }
+        exit(0);
+    }
 
     let action = matches.value_of("action").unwrap();
     let org_file = matches.value_of("org_file").unwrap();
+    if matches.is_present("watch") {
+        let job = match (action, matches.value_of("job")) {
+            ("run", Some(job)) => job,
+            _ => {
+                println!("--watch needs run and a job");
+                exit(1);
+            }
+        };
+        let dir_base = dir_base(org_file, matches.is_present("from_cwd"))?;
+        //every run is a new orgjob with the same arguments, which reads the
+        //org file again
+        let args = without_option(std::env::args_os().skip(1).collect(), "--watch");
+        let files = || watched_files(Path::new(org_file), job, ".", &dir_base);
+        match watch_command(&std::env::current_exe()?, &args, files) {
+            Ok(code) => exit(code),
+            Err(e) => {
+                println!("failed to watch {}: {}", job, e);
+                exit(1);
+            }
+        }
+    }
+    if action == "daemon" {
+        if matches.is_present("job") {
+            println!("daemon runs every scheduled heading and takes no job");
+            exit(1);
+        }
+        //runs get the same options, the action coming last
+        let mut args = without_option(std::env::args_os().skip(1).collect(), "--logbook");
+        let end = args.iter().position(|a| a == "--").unwrap_or(args.len());
+        if let Some(i) = args[..end].iter().rposition(|a| a == "daemon") {
+            args.remove(i);
+        }
+        let logbook = matches.is_present("logbook");
+        match run_daemon(
+            Path::new(org_file),
+            ".",
+            &std::env::current_exe()?,
+            &args,
+            logbook,
+        ) {
+            Ok(code) => exit(code),
+            Err(e) => {
+                println!("daemon failed: {}", e);
+                exit(1);
+            }
+        }
+    }
     let mut reader = BufReader::new(match File::open(org_file) {
         Ok(f) => f,
         Err(_) => {
@@ -92,72 +1687,319 @@ fn main() -> Result<()> {
     };
 
     let sep = ".";
+    let run_id = new_run_id();
+    let mut env_overrides = Vec::new();
+    for kv in matches.values_of("env").into_iter().flatten() {
+        match kv.find('=') {
+            Some(i) if i > 0 => set_env(&mut env_overrides, &kv[..i], &kv[i + 1..]),
+            _ => {
+                println!("invalid --env, expected KEY=VALUE: {}", kv);
+                exit(1);
+            }
+        }
+    }
+    let dir_base = dir_base(org_file, matches.is_present("from_cwd"))?;
 
     match matches.value_of("job") {
         Some(job) => {
-            let query: Vec<&str> = job.split(sep).collect();
-            let nodes = doc.lookup_nodes(DOC_NODE_ROOT_ID, &query);
+            let nodes = find_jobs(&doc, job, sep);
             match nodes.len() {
                 0 => {
-                    println!("no matches for: {:?}", query);
+                    println!("no matches for: {:?}", job);
                     exit(1);
                 }
                 1 => {
                     let n = nodes[0];
-                    let node = doc.get_node(n);
-                    let code = doc.get_runnable_code(n, sep);
-                    let mut selected_code = None;
-                    match code.len() {
-                        0 => {
-                            println!("no code avaiable in {} for {:?}", node.name(), query);
+                    if action == "history" {
+                        print_history(Path::new(org_file), Some(&doc.get_fullname(n).join(sep)));
+                        exit(0);
+                    }
+                    if action == "log" {
+                        if let Some(vs) = matches.values_of("args") {
+                            let vs: Vec<&str> = vs.collect();
+                            println!("unexpected arguments for log: {}", vs.join(" "));
                             exit(1);
                         }
-                        1 => {
-                            selected_code = Some(&code[0]);
-                        }
-                        _ => {
-                            if let Some(lang) = matches.value_of("lang") {
-                                for c in code.iter() {
-                                    if c.interpreter == lang {
-                                        selected_code = Some(c);
-                                        break;
-                                    }
+                        print_logs(
+                            Path::new(org_file),
+                            &doc.get_fullname(n).join(sep),
+                            matches.is_present("last"),
+                        );
+                        exit(0);
+                    }
+                    if action == "graph" {
+                        print_dependency_graph(&doc, &[n], sep);
+                        exit(0);
+                    }
+                    if action == "tangle" {
+                        let mut nodes = vec![n];
+                        nodes.extend(doc.get_descendants(n));
+                        tangle(&doc, &nodes, Path::new(org_file));
+                        exit(0);
+                    }
+                    let lang = matches.value_of("lang");
+                    let code = doc.get_runnable_code(n, sep);
+                    if action == "run" || action == "export" {
+                        let mut order = match doc.get_dependency_order(n, sep) {
+                            Ok(order) => order,
+                            Err(e) => {
+                                print_dependency_error(&doc, &e, sep);
+                                exit(1);
+                            }
+                        };
+                        let stdin_from = matches.value_of("stdin_from");
+                        //the job given with --stdin-from job:PATH runs first
+                        if let Some(path) = stdin_from.and_then(|v| v.strip_prefix("job:")) {
+                            let source = match find_jobs(&doc, path, sep)[..] {
+                                [node] => node,
+                                _ => {
+                                    println!("--stdin-from needs exactly one job: {}", path);
+                                    exit(1);
                                 }
-                                if let None = selected_code {
-                                    println!("no match for: {:?} with lang: {}", query, lang);
+                            };
+                            let mut before = match doc.get_dependency_order(source, sep) {
+                                Ok(before) => before,
+                                Err(e) => {
+                                    print_dependency_error(&doc, &e, sep);
                                     exit(1);
                                 }
+                            };
+                            if before.contains(&n) {
+                                println!("{} cannot read its own output", path);
+                                exit(1);
+                            }
+                            order.retain(|x| !before.contains(x));
+                            before.extend(order);
+                            order = before;
+                        }
+                        if action == "export" {
+                            let settings = RunSettings {
+                                org_file: Path::new(org_file),
+                                lang,
+                                sep,
+                                parallel: false,
+                                force: true,
+                                timeout: None,
+                                dir_base: &dir_base,
+                                env: &env_overrides,
+                                run_id: None,
+                                pipeline: matches.is_present("pipeline"),
+                                stdin_from: None,
+                                dry_run: false,
+                            };
+                            let params = if settings.pipeline {
+                                pipeline_code(&doc, n, sep).1.params
                             } else {
-                                println!("mutliple languages in the matched block; use -l/--lang");
-                                for c in &code {
-                                    println!("{}", c.interpreter);
+                                select_code(&doc, n, &code, &job, lang).params.clone()
+                            };
+                            let tasks = build_tasks(&doc, &order, None, &settings);
+                            check_export(&doc, &order, &tasks, &settings);
+                            //the dependencies that have code come first, one task each
+                            let first = order[..order.len() - 1]
+                                .iter()
+                                .filter(|d| !doc.get_runnable_code(**d, sep).is_empty())
+                                .count();
+                            let script_dir = match matches.value_of("output") {
+                                Some(path) => match Path::new(path).parent() {
+                                    Some(dir) if dir != Path::new("") => {
+                                        std::fs::canonicalize(dir)?
+                                    }
+                                    _ => std::env::current_dir()?,
+                                },
+                                None => std::env::current_dir()?,
+                            };
+                            let script = export_script(
+                                org_file,
+                                &doc.get_fullname(n).join(sep),
+                                &tasks,
+                                first,
+                                &params,
+                                &ExportPlace {
+                                    script_dir: &script_dir,
+                                    base: &dir_base,
+                                },
+                            );
+                            match matches.value_of("output") {
+                                Some(path) => {
+                                    if let Err(e) = write_script(Path::new(path), &script) {
+                                        println!("failed to write {}: {}", path, e);
+                                        exit(1);
+                                    }
+                                    println!("exported to {}", path);
                                 }
-                                exit(1);
+                                None => print!("{}", script),
                             }
+                            exit(0);
                         }
-                    };
-                    let c = selected_code.unwrap();
-                    if action == "run" {
-                        let args: Vec<_> = match matches.values_of("args") {
-                            Some(vs) => vs.collect(),
+                        let mut args: Vec<String> = match matches.values_of("args") {
+                            Some(vs) => vs.map(|v| v.to_string()).collect(),
                             None => vec![],
                         };
-                        match run_code(&c.interpreter, &c.code.join("\n"), &args)?.code() {
-                            Some(code) => exit(code),
-                            None => {
-                                println!("subprocess killed");
-                                exit(1);
+                        if matches.is_present("help") {
+                            args.push("--help".to_string());
+                        }
+                        let jobs = match matches.value_of("jobs") {
+                            Some(jobs) => match jobs.parse::<usize>() {
+                                Ok(j) if j > 0 => Some(j),
+                                _ => {
+                                    println!("invalid number of jobs: {}", jobs);
+                                    exit(1);
+                                }
+                            },
+                            None => None,
+                        };
+                        let timeout = match matches.value_of("timeout") {
+                            Some(t) => match parse_duration(t) {
+                                Some(d) => Some(d),
+                                None => {
+                                    println!("invalid timeout: {}", t);
+                                    exit(1);
+                                }
+                            },
+                            None => None,
+                        };
+                        let settings = RunSettings {
+                            org_file: Path::new(org_file),
+                            lang,
+                            sep,
+                            parallel: jobs.is_some(),
+                            force: matches.is_present("force"),
+                            timeout,
+                            dir_base: &dir_base,
+                            env: &env_overrides,
+                            run_id: Some(&run_id),
+                            pipeline: matches.is_present("pipeline"),
+                            stdin_from,
+                            dry_run: matches.is_present("dry_run"),
+                        };
+                        let report = matches.values_of("report").map(|vs| {
+                            let vs: Vec<&str> = vs.collect();
+                            match ReportFormat::parse(vs[0]) {
+                                Some(format) => (format, Path::new(vs[1])),
+                                None => {
+                                    println!("invalid report format: {}, use json or junit", vs[0]);
+                                    exit(1);
+                                }
+                            }
+                        });
+                        let tasks = build_tasks(&doc, &order, Some(&args), &settings);
+                        let job = doc.get_fullname(n).join(sep);
+                        let trace = matches.is_present("trace");
+                        if trace {
+                            check_trace(&tasks);
+                        }
+                        let lock_key = job_lock(&doc, n, &job, matches.is_present("lock"));
+                        if matches.is_present("dry_run") {
+                            let cwd = std::env::current_dir()?;
+                            print!("{}", dry_run_plan(&job, Path::new(org_file), &cwd, &tasks));
+                            if let Some(key) = &lock_key {
+                                let path = job_lock_path(Path::new(org_file), key);
+                                match read_lock(&path) {
+                                    Some(h) => println!("\nlock: {}, held by {}", key, h),
+                                    None => println!("\nlock: {}", key),
+                                }
+                            }
+                            exit(0);
+                        }
+                        confirm_run(&job, &tasks, matches.is_present("yes"));
+                        let lock = lock_key.map(|key| {
+                            take_lock(Path::new(org_file), &key, matches.is_present("wait"))
+                        });
+                        let mut options = ScheduleOptions {
+                            jobs: jobs.unwrap_or(1),
+                            fail_fast: jobs.is_none() || matches.is_present("fail_fast"),
+                            prefix_output: jobs.is_some(),
+                            clean_env: matches.is_present("clean_env"),
+                            log: if matches.is_present("no_log") {
+                                None
+                            } else {
+                                open_run_log(Path::new(org_file), &run_id, &job)
+                            },
+                            keep_scripts: if matches.is_present("keep_script") {
+                                Some(match matches.value_of("keep_script") {
+                                    Some(dir) => PathBuf::from(dir),
+                                    None => std::env::temp_dir()
+                                        .join(format!("orgjob-{}", safe_file_name(&run_id))),
+                                })
+                            } else {
+                                None
+                            },
+                            trace,
+                            //a report needs the output
+                            terminal: matches.is_present("terminal")
+                                && report.is_none()
+                                && is_tty(libc::STDOUT_FILENO),
+                        };
+                        //a report needs the output even when the run is not logged
+                        let report_dir = match (&report, &options.log) {
+                            (Some(_), None) => Some(tempfile::tempdir()?),
+                            _ => None,
+                        };
+                        if let Some(dir) = &report_dir {
+                            options.log = Some(RunLog::create(
+                                dir.path(),
+                                &run_id,
+                                &job,
+                                Path::new(org_file),
+                            )?);
+                        }
+                        let started = Instant::now();
+                        let runs = schedule_tasks(&tasks, &options);
+                        let duration = started.elapsed();
+                        let status: Vec<TaskStatus> =
+                            runs.iter().map(|r| r.status.clone()).collect();
+                        let code = aggregate_status(&status);
+                        if let (Some(log), None) = (&options.log, &report_dir) {
+                            if let Err(e) = log.finish(&tasks, &status, code) {
+                                println!("failed to write {}: {}", log.dir.display(), e);
+                            }
+                        }
+                        if let (Some((format, path)), Some(log)) = (report, &options.log) {
+                            if let Err(e) =
+                                write_report(path, format, &job, &tasks, &runs, log, duration)
+                            {
+                                println!("failed to write report {}: {}", path.display(), e);
+                            }
+                        }
+                        if jobs.is_some() {
+                            for (t, s) in tasks.iter().zip(status.iter()) {
+                                println!("{}: {}", t.name, s);
+                            }
+                        } else if let Some(i) = status.iter().position(|s| {
+                            !s.is_success()
+                                && !matches!(s, TaskStatus::Skipped | TaskStatus::Interrupted(_))
+                        }) {
+                            match &status[i] {
+                                TaskStatus::Killed(_)
+                                | TaskStatus::TimedOut(_)
+                                | TaskStatus::LimitExceeded(_) => {
+                                    println!("{}: {}", tasks[i].name, status[i])
+                                }
+                                TaskStatus::Error(e) => {
+                                    println!("failed to run {}: {}", tasks[i].name, e)
+                                }
+                                _ => {}
+                            }
+                            if i + 1 < tasks.len() {
+                                println!("dependency failed: {}", tasks[i].name);
+                            }
+                        }
+                        if let Some(lock) = lock {
+                            if let Err(e) = lock.release() {
+                                println!("failed to release the lock: {}", e);
                             }
                         }
+                        exit(code);
                     } else {
                         //show
+                        let c = select_code(&doc, n, &code, &job, lang);
                         println!("#!/usr/bin/env {}", c.interpreter);
                         println!("{}", c.code.join("\n"));
                         exit(0);
                     }
                 }
                 _ => {
-                    println!("multiple matches for: {:?}", query);
+                    println!("multiple matches for: {:?}", job);
                     for n in nodes {
                         println!("{}", doc.get_fullname(n).join(sep));
                     }
@@ -166,6 +2008,20 @@ fn main() -> Result<()> {
             }
         }
         None => {
+            if action == "history" {
+                print_history(Path::new(org_file), None);
+                exit(0);
+            }
+            if action == "graph" {
+                let all: Vec<DocNodeId> = ((DOC_NODE_ROOT_ID + 1)..=doc.len()).collect();
+                print_dependency_graph(&doc, &all, sep);
+                exit(0);
+            }
+            if action == "tangle" {
+                let all: Vec<DocNodeId> = ((DOC_NODE_ROOT_ID + 1)..=doc.len()).collect();
+                tangle(&doc, &all, Path::new(org_file));
+                exit(0);
+            }
             if action != "list" {
                 println!("job name not provided");
                 exit(1);
@@ -0,0 +1,71 @@
+///what a secret is replaced with in the output of a job
+pub const MASK: &str = "****";
+
+///replaces the values of secrets in a stream of output. The end of a chunk
+///that could be the start of a secret is held back until the next chunk
+///tells, so that a value split across reads is masked all the same.
+#[derive(Clone, Debug, Default)]
+pub struct Masker {
+    secrets: Vec<Vec<u8>>,
+    pending: Vec<u8>,
+}
+
+impl Masker {
+    pub fn new(secrets: &[String]) -> Masker {
+        let mut secrets: Vec<Vec<u8>> = secrets
+            .iter()
+            .filter(|s| !s.is_empty())
+            .map(|s| s.as_bytes().to_vec())
+            .collect();
+        //the longest first, so that a secret containing another is masked whole
+        secrets.sort_by(|a, b| b.len().cmp(&a.len()).then(a.cmp(b)));
+        secrets.dedup();
+        return Masker {
+            secrets,
+            pending: Vec::new(),
+        };
+    }
+
+    fn mask(&mut self, chunk: &[u8], last: bool) -> Vec<u8> {
+        if self.secrets.is_empty() {
+            return chunk.to_vec();
+        }
+        let mut buf = std::mem::take(&mut self.pending);
+        buf.extend_from_slice(chunk);
+        let mut out = Vec::with_capacity(buf.len());
+        let mut i = 0;
+        while i < buf.len() {
+            let rest = &buf[i..];
+            if !last
+                && self
+                    .secrets
+                    .iter()
+                    .any(|s| s.len() > rest.len() && s.starts_with(rest))
+            {
+                break;
+            }
+            match self.secrets.iter().find(|s| rest.starts_with(s)) {
+                Some(s) => {
+                    out.extend_from_slice(MASK.as_bytes());
+                    i += s.len();
+                }
+                None => {
+                    out.push(buf[i]);
+                    i += 1;
+                }
+            }
+        }
+        self.pending = buf[i..].to_vec();
+        return out;
+    }
+
+    ///the masked output that can be written after this chunk
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<u8> {
+        return self.mask(chunk, false);
+    }
+
+    ///the masked rest of the output, with whatever was held back
+    pub fn finish(&mut self, chunk: &[u8]) -> Vec<u8> {
+        return self.mask(chunk, true);
+    }
+}
@@ -1,6 +1,7 @@
 extern crate regex;
 
 use super::code_doc::*;
+use super::timestamp::*;
 use regex::Regex;
 use std::io::BufRead;
 
@@ -9,6 +10,27 @@ pub enum DocParseError {
     BlockNotClosed { linum: usize, line: String },
     UnexpectedLevel { linum: usize, line: String },
     UnexpectedLine { linum: usize, line: String },
+    DrawerNotClosed { linum: usize, line: String },
+}
+
+///a line with only SCHEDULED, DEADLINE and CLOSED timestamps, as the
+///planning line after a heading
+pub const PLANNING_RE: &str = r"^\s*(?:(?:SCHEDULED|DEADLINE|CLOSED):\s*[<\[][^>\]]*[>\]]\s*)+$";
+
+///split `:key value :other value` into pairs; a value runs up to the next `:key`
+pub fn parse_header_args(args: &str) -> Vec<(String, String)> {
+    let mut result: Vec<(String, String)> = Vec::new();
+    for word in args.split_whitespace() {
+        if word.len() > 1 && word.starts_with(':') {
+            result.push((word[1..].to_string(), String::new()));
+        } else if let Some((_, value)) = result.last_mut() {
+            if !value.is_empty() {
+                value.push(' ');
+            }
+            value.push_str(word);
+        }
+    }
+    return result;
 }
 
 pub fn parse_org_doc<T: BufRead>(
@@ -17,15 +39,26 @@ pub fn parse_org_doc<T: BufRead>(
     default_lang: &str,
 ) -> Result<CodeDoc, DocParseError> {
     let mut doc = CodeDoc::new();
-    let begin_src_re = Regex::new(r"^#\+(?i)BEGIN_SRC(?:\s+(\w+))?(?:\s+.*)?").unwrap();
+    let begin_src_re = Regex::new(r"^#\+(?i)BEGIN_SRC(?:\s+(\w+))?(\s+.*)?").unwrap();
     let end_src_re = Regex::new(r"^#\+(?i)END_SRC(?:\s+.*)?").unwrap();
     let hdr_re = Regex::new(r"(\*+) (.+)").unwrap();
+    let prop_begin_re = Regex::new(r"^\s*(?i):PROPERTIES:\s*$").unwrap();
+    let prop_end_re = Regex::new(r"^\s*(?i):END:\s*$").unwrap();
+    let prop_re = Regex::new(r"^\s*:([^:\s]+):(?:\s+(.*))?$").unwrap();
+    let keyword_re = Regex::new(r"^#\+(\w+):\s*(.*?)\s*$").unwrap();
+    let begin_example_re = Regex::new(r"^\s*#\+(?i)BEGIN_EXAMPLE(?:\s+.*)?$").unwrap();
+    let end_example_re = Regex::new(r"^\s*#\+(?i)END_EXAMPLE\s*$").unwrap();
+    let tags_re = Regex::new(r"^(.*?)\s+:((?:[\w@#%]+:)+)\s*$").unwrap();
+    let planning_re = Regex::new(PLANNING_RE).unwrap();
+    let scheduled_re = Regex::new(r"SCHEDULED:\s*(<[^>]*>)").unwrap();
 
     #[derive(PartialEq, Copy, Clone, Debug)]
     enum State {
         TEXT,
         SRC,
-    };
+        PROPERTIES,
+        EXAMPLE,
+    }
 
     let mut state = State::TEXT;
 
@@ -33,10 +66,21 @@ pub fn parse_org_doc<T: BufRead>(
     let mut current_level = 0i32;
     let mut current_hdr = docname;
     let mut code_blocks = Vec::new();
+    let mut properties = Vec::new();
+    let mut tags = Vec::new();
+    let mut hdr_linum = 0;
+    let mut scheduled = None;
 
     let mut code_hdr_line = String::from("invalid");
     let mut code_lines = Vec::new();
     let mut interpreter = String::from("invalid");
+    let mut header_args = Vec::new();
+    let mut code_linum = 0;
+    let mut code_name = None;
+    //a #+NAME: line waiting for the block it names
+    let mut block_name = None;
+    let mut drawer_linum = 0;
+    let mut drawer_line = String::new();
 
     let mut linum = 0;
     for line_res in f.lines() {
@@ -50,13 +94,31 @@ pub fn parse_org_doc<T: BufRead>(
                         .and_then(|x| Some(x.as_str()))
                         .unwrap_or(default_lang)
                         .to_string();
+                    header_args = parse_header_args(caps.get(2).map(|x| x.as_str()).unwrap_or(""));
                     code_lines = Vec::new();
                     state = State::SRC;
                     code_hdr_line = line.to_string();
+                    code_linum = linum;
+                    code_name = block_name.take();
+                } else if begin_example_re.is_match(&line) {
+                    state = State::EXAMPLE;
+                    code_lines = Vec::new();
+                    code_hdr_line = line.to_string();
+                    code_name = block_name.take();
+                } else if prop_begin_re.is_match(&line) {
+                    state = State::PROPERTIES;
+                    drawer_linum = linum;
+                    drawer_line = line.to_string();
                 } else if let Some(caps) = hdr_re.captures(&line) {
                     let new_level = caps[1].len() as i32;
 
                     parent = doc.add(parent, current_hdr, current_level, code_blocks);
+                    for (key, value) in properties {
+                        doc.set_property(parent, key, value);
+                    }
+                    doc.set_tags(parent, tags);
+                    doc.set_linum(parent, hdr_linum);
+                    doc.set_scheduled(parent, scheduled.take());
                     if new_level <= current_level + 1 {
                         for _ in 0..(current_level - new_level + 1) {
                             parent = doc.get_parent(parent).unwrap();
@@ -69,36 +131,109 @@ pub fn parse_org_doc<T: BufRead>(
                     }
 
                     current_level = new_level;
-                    current_hdr = caps[2].to_string();
+                    match tags_re.captures(&caps[2]) {
+                        Some(t) => {
+                            current_hdr = t[1].to_string();
+                            tags = t[2]
+                                .split(':')
+                                .filter(|x| !x.is_empty())
+                                .map(|x| x.to_string())
+                                .collect();
+                        }
+                        None => {
+                            current_hdr = caps[2].to_string();
+                            tags = Vec::new();
+                        }
+                    }
                     code_blocks = Vec::new();
+                    properties = Vec::new();
+                    hdr_linum = linum;
+                } else if planning_re.is_match(&line) {
+                    if let Some(caps) = scheduled_re.captures(&line) {
+                        match parse_timestamp(&caps[1]) {
+                            Some(ts) => scheduled = Some(ts),
+                            None => doc.add_unscheduled(linum, line),
+                        }
+                    }
                 } else if end_src_re.is_match(&line) {
                     return Err(DocParseError::UnexpectedLine {
                         linum: linum,
                         line: line,
                     });
+                } else if let Some(caps) = keyword_re.captures(&line) {
+                    if caps[1].eq_ignore_ascii_case("name") {
+                        block_name = Some(caps[2].to_string());
+                        continue;
+                    }
+                    doc.add_keyword(caps[1].to_string(), caps[2].to_string());
                 }
+                block_name = None;
             }
             State::SRC => {
                 if end_src_re.is_match(&line) {
                     code_blocks.push(CodeBlock {
                         interpreter: interpreter.to_string(),
                         code: code_lines.join("\n"),
+                        header_args: std::mem::take(&mut header_args),
+                        linum: code_linum,
+                        name: code_name.take(),
                     });
                     state = State::TEXT;
                 } else {
                     code_lines.push(line);
                 }
             }
+            State::EXAMPLE => {
+                if end_example_re.is_match(&line) {
+                    if let Some(name) = code_name.take() {
+                        doc.add_example(name, code_lines.join("\n") + "\n");
+                    }
+                    state = State::TEXT;
+                } else if line.starts_with(",*") || line.starts_with(",#+") {
+                    //org escapes lines that would be read as headings or keywords
+                    code_lines.push(line[1..].to_string());
+                } else {
+                    code_lines.push(line);
+                }
+            }
+            State::PROPERTIES => {
+                if prop_end_re.is_match(&line) {
+                    state = State::TEXT;
+                } else if let Some(caps) = prop_re.captures(&line) {
+                    properties.push((
+                        caps[1].to_string(),
+                        caps.get(2)
+                            .map(|x| x.as_str().trim())
+                            .unwrap_or("")
+                            .to_string(),
+                    ));
+                } else {
+                    return Err(DocParseError::UnexpectedLine { linum, line });
+                }
+            }
         }
     }
 
-    if state == State::SRC {
+    if state == State::SRC || state == State::EXAMPLE {
         return Err(DocParseError::BlockNotClosed {
             linum: linum - code_lines.len(),
             line: code_hdr_line,
         });
     }
 
-    doc.add(parent, current_hdr, current_level, code_blocks);
+    if state == State::PROPERTIES {
+        return Err(DocParseError::DrawerNotClosed {
+            linum: drawer_linum,
+            line: drawer_line,
+        });
+    }
+
+    let last = doc.add(parent, current_hdr, current_level, code_blocks);
+    for (key, value) in properties {
+        doc.set_property(last, key, value);
+    }
+    doc.set_tags(last, tags);
+    doc.set_linum(last, hdr_linum);
+    doc.set_scheduled(last, scheduled);
     return Ok(doc);
 }
@@ -0,0 +1,272 @@
+use std::fmt;
+
+///a named job parameter, declared with `:var name=default` or an ARGS property
+#[derive(PartialEq, Clone, Debug)]
+pub struct Param {
+    pub name: String,
+    pub default: Option<String>,
+    ///allowed values, from the CHOICES property
+    pub choices: Vec<String>,
+    ///read without echo when prompted, from the SECRET property
+    pub secret: bool,
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub enum ParamError {
+    InvalidName(String),
+    Reserved(String),
+    Unknown(String),
+    NoValue(String),
+    Missing(String),
+    NoTerminal(String),
+    InvalidChoice { name: String, value: String },
+}
+
+impl fmt::Display for ParamError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParamError::InvalidName(name) => write!(f, "invalid parameter name: {}", name),
+            ParamError::Reserved(name) => write!(
+                f,
+                "parameter {} would replace a variable of the environment, use another name",
+                name
+            ),
+            ParamError::Unknown(name) => write!(f, "unknown parameter: --{}", name),
+            ParamError::NoValue(name) => write!(f, "no value for parameter: --{}", name),
+            ParamError::Missing(name) => write!(f, "missing parameter: --{}", name),
+            ParamError::NoTerminal(name) => write!(
+                f,
+                "missing parameter: --{}, and stdin is not a terminal to ask for it",
+                name
+            ),
+            ParamError::InvalidChoice { name, value } => {
+                write!(f, "invalid value for --{}: {}", name, value)
+            }
+        }
+    }
+}
+
+///job arguments matched against the declared parameters
+#[derive(PartialEq, Clone, Debug, Default)]
+pub struct BoundParams {
+    pub values: Vec<(String, String)>,
+    pub positional: Vec<String>,
+    pub help: bool,
+    ///parameters without a value, which a dry run does not ask for
+    pub unbound: Vec<String>,
+}
+
+///split on whitespace, keeping double quoted text together without the quotes
+pub fn split_words(s: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut quoted = false;
+    for c in s.chars() {
+        if c == '"' {
+            quoted = !quoted;
+            in_word = true;
+        } else if c.is_whitespace() && !quoted {
+            if in_word {
+                words.push(std::mem::take(&mut word));
+                in_word = false;
+            }
+        } else {
+            word.push(c);
+            in_word = true;
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    return words;
+}
+
+///parse `name other=default` declarations
+pub fn parse_params(decls: &str) -> Vec<Param> {
+    return split_words(decls)
+        .into_iter()
+        .map(|w| match w.find('=') {
+            Some(i) => Param {
+                name: w[..i].to_string(),
+                default: Some(w[i + 1..].to_string()),
+                choices: Vec::new(),
+                secret: false,
+            },
+            None => Param {
+                name: w,
+                default: None,
+                choices: Vec::new(),
+                secret: false,
+            },
+        })
+        .collect();
+}
+
+///apply a CHOICES property, `name=a|b|c other=x|y`, to the declared parameters
+pub fn set_param_choices(params: &mut [Param], decls: &str) {
+    for w in split_words(decls) {
+        if let Some(i) = w.find('=') {
+            if let Some(p) = params.iter_mut().find(|p| p.name == w[..i]) {
+                p.choices = w[i + 1..].split('|').map(|c| c.to_string()).collect();
+            }
+        }
+    }
+}
+
+///apply a SECRET property, a list of parameter names
+pub fn set_param_secrets(params: &mut [Param], names: &str) {
+    for name in names.split_whitespace() {
+        if let Some(p) = params.iter_mut().find(|p| p.name == name) {
+            p.secret = true;
+        }
+    }
+}
+
+///variables a job needs from its environment, which parameters are exported as
+const RESERVED: &[&str] = &[
+    "PATH", "HOME", "USER", "LOGNAME", "SHELL", "PWD", "OLDPWD", "IFS", "TERM", "LANG", "TMPDIR",
+    "ENV", "CDPATH",
+];
+const RESERVED_PREFIXES: &[&str] = &["ORGJOB_", "LD_", "LC_", "BASH", "PYTHON"];
+
+fn is_reserved_name(name: &str) -> bool {
+    return RESERVED.contains(&name) || RESERVED_PREFIXES.iter().any(|p| name.starts_with(p));
+}
+
+fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    return match chars.next() {
+        Some(c) => {
+            (c.is_ascii_alphabetic() || c == '_')
+                && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        None => false,
+    };
+}
+
+///match `--name value` and `--name=value` arguments against the declared
+///parameters; everything else is passed on as positional arguments. Jobs
+///without declared parameters get all arguments unchanged.
+#[cfg(test)]
+pub fn bind_params<S: AsRef<str>>(params: &[Param], args: &[S]) -> Result<BoundParams, ParamError> {
+    return bind_params_with(params, args, |p| Err(ParamError::Missing(p.name.clone())));
+}
+
+///like bind_params, asking for the value of parameters that have neither
+///an argument nor a default
+pub fn bind_params_with<S: AsRef<str>, F: FnMut(&Param) -> Result<String, ParamError>>(
+    params: &[Param],
+    args: &[S],
+    mut ask: F,
+) -> Result<BoundParams, ParamError> {
+    for p in params {
+        if !is_valid_name(&p.name) {
+            return Err(ParamError::InvalidName(p.name.clone()));
+        }
+        if is_reserved_name(&p.name) {
+            return Err(ParamError::Reserved(p.name.clone()));
+        }
+    }
+    let mut bound = BoundParams::default();
+    let mut given: Vec<(String, String)> = Vec::new();
+    let mut i = 0;
+    while i < args.len() {
+        let arg = args[i].as_ref();
+        i += 1;
+        if arg == "--" {
+            bound
+                .positional
+                .extend(args[i..].iter().map(|a| a.as_ref().to_string()));
+            break;
+        }
+        if arg == "--help" || arg == "-h" {
+            bound.help = true;
+            continue;
+        }
+        let flag = match arg.strip_prefix("--") {
+            Some(flag) if !params.is_empty() => flag,
+            _ => {
+                bound.positional.push(arg.to_string());
+                continue;
+            }
+        };
+        let (name, value) = match flag.find('=') {
+            Some(k) => (&flag[..k], Some(flag[k + 1..].to_string())),
+            None => (flag, None),
+        };
+        if !params.iter().any(|p| p.name == name) {
+            return Err(ParamError::Unknown(name.to_string()));
+        }
+        let value = match value {
+            Some(v) => v,
+            None => {
+                if i >= args.len() {
+                    return Err(ParamError::NoValue(name.to_string()));
+                }
+                i += 1;
+                args[i - 1].as_ref().to_string()
+            }
+        };
+        given.retain(|(n, _)| n != name);
+        given.push((name.to_string(), value));
+    }
+    if bound.help {
+        return Ok(bound);
+    }
+    for p in params {
+        let value = match given.iter().find(|(n, _)| *n == p.name) {
+            Some((_, v)) => {
+                if !p.choices.is_empty() && !p.choices.contains(v) {
+                    return Err(ParamError::InvalidChoice {
+                        name: p.name.clone(),
+                        value: v.clone(),
+                    });
+                }
+                v.clone()
+            }
+            None => match &p.default {
+                Some(d) => d.clone(),
+                None => ask(p)?,
+            },
+        };
+        bound.values.push((p.name.clone(), value));
+    }
+    return Ok(bound);
+}
+
+pub fn params_help(job: &str, params: &[Param]) -> String {
+    let mut usage = format!("usage: orgjob run {}", job);
+    for p in params {
+        if p.default.is_some() {
+            usage.push_str(&format!(" [--{} VALUE]", p.name));
+        } else {
+            usage.push_str(&format!(" --{} VALUE", p.name));
+        }
+    }
+    usage.push_str(" [ARGS...]\n");
+    if params.is_empty() {
+        return usage;
+    }
+    usage.push_str("\nparameters:\n");
+    let width = params.iter().map(|p| p.name.len()).max().unwrap_or(0);
+    for p in params {
+        let mut note = match &p.default {
+            Some(d) => format!("default: {}", d),
+            None => "required".to_string(),
+        };
+        if !p.choices.is_empty() {
+            note.push_str(&format!(", one of: {}", p.choices.join(", ")));
+        }
+        if p.secret {
+            note.push_str(", secret");
+        }
+        usage.push_str(&format!(
+            "    --{:width$}  {}\n",
+            p.name,
+            note,
+            width = width
+        ));
+    }
+    return usage;
+}
@@ -0,0 +1,90 @@
+extern crate libc;
+
+use super::params::*;
+use std::io::{stderr, stdin, BufRead, Write};
+
+pub fn stdin_is_tty() -> bool {
+    return unsafe { libc::isatty(libc::STDIN_FILENO) } == 1;
+}
+
+///turns terminal echo off until dropped
+struct NoEcho {
+    saved: Option<libc::termios>,
+}
+
+impl NoEcho {
+    fn new() -> NoEcho {
+        unsafe {
+            let mut term: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut term) != 0 {
+                return NoEcho { saved: None };
+            }
+            let saved = term;
+            term.c_lflag &= !libc::ECHO;
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &term);
+            return NoEcho { saved: Some(saved) };
+        }
+    }
+}
+
+impl Drop for NoEcho {
+    fn drop(&mut self) {
+        if let Some(saved) = &self.saved {
+            unsafe {
+                libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, saved);
+            }
+        }
+    }
+}
+
+fn read_line(secret: bool) -> Option<String> {
+    let _no_echo = if secret { Some(NoEcho::new()) } else { None };
+    let mut line = String::new();
+    let n = stdin().lock().read_line(&mut line).ok()?;
+    if secret {
+        eprintln!();
+    }
+    if n == 0 {
+        return None;
+    }
+    return Some(line.trim_end_matches(&['\n', '\r'][..]).to_string());
+}
+
+///ask for a parameter value on the terminal; choices can be picked by
+///number or by value
+pub fn prompt_param(p: &Param) -> Result<String, ParamError> {
+    if !stdin_is_tty() {
+        return Err(ParamError::NoTerminal(p.name.clone()));
+    }
+    for (i, c) in p.choices.iter().enumerate() {
+        eprintln!("  {}) {}", i + 1, c);
+    }
+    loop {
+        eprint!("{}: ", p.name);
+        let _ = stderr().flush();
+        let value = match read_line(p.secret) {
+            Some(v) => v,
+            None => return Err(ParamError::Missing(p.name.clone())),
+        };
+        if p.choices.is_empty() {
+            if !value.is_empty() {
+                return Ok(value);
+            }
+            continue;
+        }
+        if p.choices.contains(&value) {
+            return Ok(value);
+        }
+        match value.parse::<usize>() {
+            Ok(i) if i >= 1 && i <= p.choices.len() => return Ok(p.choices[i - 1].clone()),
+            _ => eprintln!("choose one of: {}", p.choices.join(", ")),
+        }
+    }
+}
+
+///ask to type phrase before a dangerous run; false when anything else is typed
+pub fn confirm(phrase: &str) -> bool {
+    eprint!("type {} to continue: ", phrase);
+    let _ = stderr().flush();
+    return read_line(false).is_some_and(|l| l.trim() == phrase);
+}
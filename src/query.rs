@@ -0,0 +1,247 @@
+extern crate regex;
+
+use regex::Regex;
+use std::fmt;
+
+///how one segment of a job path matches the name of a heading
+#[derive(Clone, Debug)]
+pub enum NameMatch {
+    ///`build`: the name contains it
+    Contains(String),
+    ///`=build`: the name is it
+    Exact(String),
+    ///`build*`: the name starts with it
+    Prefix(String),
+    ///`deploy-?`, `*` or `[ab]*`: the name matches the glob as a whole
+    Glob(Regex),
+    ///`/^db-\d+$/`: the regex matches somewhere in the name
+    Regex(Regex),
+}
+
+impl NameMatch {
+    pub fn matches(&self, name: &str) -> bool {
+        return match self {
+            NameMatch::Contains(s) => name.contains(s.as_str()),
+            NameMatch::Exact(s) => name == s,
+            NameMatch::Prefix(s) => name.starts_with(s.as_str()),
+            NameMatch::Glob(re) => re.is_match(name),
+            NameMatch::Regex(re) => re.is_match(name),
+        };
+    }
+}
+
+///a job path like `deploy.*.prod`. Unanchored, its segments match headings
+///in order along a path, skipping any levels between them; anchored with a
+///leading `^`, they are the path from the top, levels below the document.
+#[derive(Clone, Debug)]
+pub struct Query {
+    pub anchored: bool,
+    pub segments: Vec<NameMatch>,
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub enum QueryError {
+    EmptySegment { query: String },
+    RegexNotClosed { query: String },
+    InvalidPattern { query: String, error: String },
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QueryError::EmptySegment { query } => write!(f, "empty heading name in: {}", query),
+            QueryError::RegexNotClosed { query } => write!(f, "regex not closed in: {}", query),
+            QueryError::InvalidPattern { query, error } => {
+                write!(f, "invalid pattern in {}: {}", query, error)
+            }
+        }
+    }
+}
+
+///the characters of a segment, each with whether a backslash escaped it
+fn glob_chars(word: &str) -> Vec<(char, bool)> {
+    let mut result = Vec::new();
+    let mut chars = word.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some(next)) => {
+                chars.next();
+                result.push((next, true));
+            }
+            _ => result.push((c, false)),
+        }
+    }
+    return result;
+}
+
+///mark statistics cookies like `[1/3]` or `[50%]` plain, they are no sets
+fn plain_cookies(chars: &mut [(char, bool)]) {
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i] != ('[', false) {
+            i += 1;
+            continue;
+        }
+        let digits = chars[i + 1..]
+            .iter()
+            .take_while(|c| c.0.is_ascii_digit())
+            .count();
+        let mut end = i + 1 + digits;
+        match chars.get(end).map(|c| c.0) {
+            Some('%') => end += 1,
+            Some('/') => {
+                end += 1;
+                end += chars[end..]
+                    .iter()
+                    .take_while(|c| c.0.is_ascii_digit())
+                    .count();
+            }
+            _ => {
+                i += 1;
+                continue;
+            }
+        }
+        if chars.get(end).map(|c| c.0) == Some(']') {
+            for c in chars[i..=end].iter_mut() {
+                c.1 = true;
+            }
+            i = end;
+        }
+        i += 1;
+    }
+}
+
+fn is_wild(c: &(char, bool)) -> bool {
+    return !c.1 && (c.0 == '*' || c.0 == '?' || c.0 == '[');
+}
+
+fn literal(chars: &[(char, bool)]) -> String {
+    return chars.iter().map(|(c, _)| c).collect();
+}
+
+///a glob as a regex matching whole names: `*` is any text, `?` any one
+///character and `[...]` one of a set, `[!...]` one not in it
+fn glob_regex(glob: &[(char, bool)]) -> String {
+    let mut re = String::from("^");
+    let mut chars = glob.iter().peekable();
+    while let Some(&(c, escaped)) = chars.next() {
+        match c {
+            '*' if !escaped => re.push_str(".*"),
+            '?' if !escaped => re.push('.'),
+            '[' if !escaped && chars.clone().any(|c| c.0 == ']') => {
+                re.push('[');
+                if chars.peek() == Some(&&('!', false)) {
+                    chars.next();
+                    re.push('^');
+                }
+                for &(c, _) in chars.by_ref() {
+                    if c == ']' {
+                        break;
+                    }
+                    if c == '\\' || c == '[' {
+                        re.push('\\');
+                    }
+                    re.push(c);
+                }
+                re.push(']');
+            }
+            c => re.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    re.push('$');
+    return re;
+}
+
+fn parse_segment(word: &str, query: &str) -> Result<NameMatch, QueryError> {
+    let invalid = |e: regex::Error| QueryError::InvalidPattern {
+        query: query.to_string(),
+        error: e.to_string(),
+    };
+    if word.is_empty() {
+        return Err(QueryError::EmptySegment {
+            query: query.to_string(),
+        });
+    }
+    if let Some(name) = word.strip_prefix('=') {
+        return Ok(NameMatch::Exact(literal(&glob_chars(name))));
+    }
+    if word.len() > 1 && word.starts_with('/') && word.ends_with('/') {
+        let re = Regex::new(&word[1..word.len() - 1]).map_err(invalid)?;
+        return Ok(NameMatch::Regex(re));
+    }
+    //a backslash makes a wildcard a plain character
+    let mut chars = glob_chars(word);
+    plain_cookies(&mut chars);
+    if !chars.iter().any(is_wild) {
+        return Ok(NameMatch::Contains(literal(&chars)));
+    }
+    if let Some((last, head)) = chars.split_last() {
+        if *last == ('*', false) && !head.is_empty() && !head.iter().any(is_wild) {
+            return Ok(NameMatch::Prefix(literal(head)));
+        }
+    }
+    let re = Regex::new(&glob_regex(&chars)).map_err(invalid)?;
+    return Ok(NameMatch::Glob(re));
+}
+
+///where a segment other than a regex ends: at the first sep that no
+///backslash escapes, or the end of the path
+fn segment_end(rest: &str, sep: &str) -> usize {
+    let mut chars = rest.char_indices();
+    while let Some((i, c)) = chars.next() {
+        if c == '\\' {
+            chars.next();
+        } else if rest[i..].starts_with(sep) {
+            return i;
+        }
+    }
+    return rest.len();
+}
+
+///parse a job path, its segments separated by sep. A regex segment runs up
+///to a `/` that is followed by sep or ends the path, so it may hold sep;
+///elsewhere a backslash keeps sep in the segment.
+pub fn parse_query(text: &str, sep: &str) -> Result<Query, QueryError> {
+    let (anchored, mut rest) = match text.strip_prefix('^') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let mut segments = Vec::new();
+    loop {
+        let end = if rest.starts_with('/') {
+            let close = rest
+                .char_indices()
+                .skip(1)
+                .find(|(i, c)| {
+                    *c == '/' && !rest[..*i].ends_with('\\') && {
+                        let after = &rest[i + 1..];
+                        after.is_empty() || after.starts_with(sep)
+                    }
+                })
+                .map(|(i, _)| i + 1);
+            close.ok_or_else(|| QueryError::RegexNotClosed {
+                query: text.to_string(),
+            })?
+        } else {
+            segment_end(rest, sep)
+        };
+        segments.push(parse_segment(&rest[..end], text)?);
+        rest = &rest[end..];
+        match rest.strip_prefix(sep) {
+            Some(r) => rest = r,
+            None => break,
+        }
+    }
+    return Ok(Query { anchored, segments });
+}
+
+///an anchored query for exactly the heading with this full name, with
+///backslashes and sep in the names escaped
+pub fn exact_query(fullname: &[String], sep: &str) -> String {
+    let escaped_sep: String = sep.chars().map(|c| format!("\\{}", c)).collect();
+    let names: Vec<String> = fullname
+        .iter()
+        .map(|n| format!("={}", n.replace('\\', "\\\\").replace(sep, &escaped_sep)))
+        .collect();
+    return format!("^{}", names.join(sep));
+}
@@ -0,0 +1,205 @@
+use super::scheduler::*;
+use std::time::Duration;
+
+///captured output longer than this is cut down to its last part
+pub const REPORT_OUTPUT_LIMIT: usize = 64 * 1024;
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum ReportFormat {
+    Json,
+    Junit,
+}
+
+impl ReportFormat {
+    pub fn parse(s: &str) -> Option<ReportFormat> {
+        match s {
+            "json" => return Some(ReportFormat::Json),
+            "junit" => return Some(ReportFormat::Junit),
+            _ => return None,
+        }
+    }
+}
+
+///one step or heading of a run as it goes into a report
+pub struct ReportCase {
+    pub name: String,
+    pub interpreter: String,
+    pub run: TaskRun,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+///keep the end of the output, where the errors usually are
+pub fn truncate_output(output: &str, limit: usize) -> String {
+    if output.len() <= limit {
+        return output.to_string();
+    }
+    let mut start = output.len() - limit;
+    while !output.is_char_boundary(start) {
+        start += 1;
+    }
+    return format!("[{} bytes truncated]\n{}", start, &output[start..]);
+}
+
+fn exit_code(status: &TaskStatus) -> Option<i32> {
+    match status {
+        TaskStatus::Exited(code) => return Some(*code),
+        TaskStatus::TimedOut(_) => return Some(124),
+        TaskStatus::Killed(sig) => return Some(128 + sig),
+        TaskStatus::LimitExceeded(limit) => return Some(128 + limit.signal()),
+        _ => return None,
+    }
+}
+
+fn status_name(status: &TaskStatus) -> &'static str {
+    match status {
+        TaskStatus::Exited(0) => return "passed",
+        TaskStatus::Exited(_) => return "failed",
+        TaskStatus::UpToDate => return "up_to_date",
+        TaskStatus::TimedOut(_) => return "timed_out",
+        TaskStatus::Killed(_) => return "killed",
+        TaskStatus::LimitExceeded(_) => return "limit_exceeded",
+        TaskStatus::Skipped | TaskStatus::Interrupted(_) => return "skipped",
+        TaskStatus::Pending | TaskStatus::Running | TaskStatus::Error(_) => return "error",
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    return out;
+}
+
+fn xml_escape(s: &str) -> String {
+    let mut out = String::new();
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            //control characters other than tab and newlines are not allowed in XML 1.0
+            c if (c as u32) < 0x20 && !matches!(c, '\t' | '\n' | '\r') => out.push('?'),
+            c => out.push(c),
+        }
+    }
+    return out;
+}
+
+pub fn json_report(job: &str, status: i32, duration: Duration, cases: &[ReportCase]) -> String {
+    let mut out = String::from("{\n");
+    out.push_str(&format!("  \"job\": {},\n", json_string(job)));
+    out.push_str(&format!("  \"status\": {},\n", status));
+    out.push_str(&format!("  \"duration\": {:.3},\n", duration.as_secs_f64()));
+    out.push_str("  \"steps\": [");
+    for (i, c) in cases.iter().enumerate() {
+        out.push_str(if i == 0 { "\n" } else { ",\n" });
+        let code = match exit_code(&c.run.status) {
+            Some(code) => code.to_string(),
+            None => "null".to_string(),
+        };
+        out.push_str("    {\n");
+        out.push_str(&format!("      \"name\": {},\n", json_string(&c.name)));
+        out.push_str(&format!(
+            "      \"interpreter\": {},\n",
+            json_string(&c.interpreter)
+        ));
+        out.push_str(&format!(
+            "      \"status\": {},\n",
+            json_string(status_name(&c.run.status))
+        ));
+        out.push_str(&format!(
+            "      \"message\": {},\n",
+            json_string(&c.run.status.to_string())
+        ));
+        out.push_str(&format!("      \"exit_code\": {},\n", code));
+        out.push_str(&format!(
+            "      \"duration\": {:.3},\n",
+            c.run.duration.as_secs_f64()
+        ));
+        out.push_str(&format!("      \"attempts\": {},\n", c.run.attempts));
+        out.push_str(&format!(
+            "      \"stdout\": {},\n",
+            json_string(&truncate_output(&c.stdout, REPORT_OUTPUT_LIMIT))
+        ));
+        out.push_str(&format!(
+            "      \"stderr\": {}\n",
+            json_string(&truncate_output(&c.stderr, REPORT_OUTPUT_LIMIT))
+        ));
+        out.push_str("    }");
+    }
+    out.push_str(if cases.is_empty() { "]\n" } else { "\n  ]\n" });
+    out.push_str("}\n");
+    return out;
+}
+
+///a test suite named after the job with one test case per step or heading
+pub fn junit_report(job: &str, duration: Duration, cases: &[ReportCase]) -> String {
+    let count = |names: &[&str]| {
+        cases
+            .iter()
+            .filter(|c| names.contains(&status_name(&c.run.status)))
+            .count()
+    };
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str("<testsuites>\n");
+    out.push_str(&format!(
+        "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"{}\" skipped=\"{}\" time=\"{:.3}\">\n",
+        xml_escape(job),
+        cases.len(),
+        count(&["failed", "timed_out", "killed", "limit_exceeded"]),
+        count(&["error"]),
+        count(&["skipped", "up_to_date"]),
+        duration.as_secs_f64()
+    ));
+    for c in cases {
+        out.push_str(&format!(
+            "    <testcase name=\"{}\" classname=\"{}\" time=\"{:.3}\">\n",
+            xml_escape(&c.name),
+            xml_escape(job),
+            c.run.duration.as_secs_f64()
+        ));
+        out.push_str(&format!(
+            "      <properties><property name=\"interpreter\" value=\"{}\"/><property name=\"attempts\" value=\"{}\"/></properties>\n",
+            xml_escape(&c.interpreter),
+            c.run.attempts
+        ));
+        let message = xml_escape(&c.run.status.to_string());
+        match status_name(&c.run.status) {
+            "passed" => {}
+            "skipped" | "up_to_date" => {
+                out.push_str(&format!("      <skipped message=\"{}\"/>\n", message))
+            }
+            "error" => out.push_str(&format!("      <error message=\"{}\"/>\n", message)),
+            kind => out.push_str(&format!(
+                "      <failure message=\"{}\" type=\"{}\"/>\n",
+                message, kind
+            )),
+        }
+        for (tag, output) in [("system-out", &c.stdout), ("system-err", &c.stderr)].iter() {
+            if !output.is_empty() {
+                out.push_str(&format!(
+                    "      <{}>{}</{}>\n",
+                    tag,
+                    xml_escape(&truncate_output(output, REPORT_OUTPUT_LIMIT)),
+                    tag
+                ));
+            }
+        }
+        out.push_str("    </testcase>\n");
+    }
+    out.push_str("  </testsuite>\n</testsuites>\n");
+    return out;
+}
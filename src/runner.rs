@@ -0,0 +1,576 @@
+extern crate libc;
+extern crate tempfile;
+
+use super::interpreter::*;
+use super::mask::*;
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{stderr, stdout, BufRead, BufReader, Error, ErrorKind, Read, Result, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{spawn, JoinHandle};
+use std::time::Duration;
+use tempfile::NamedTempFile;
+
+///variables kept from orgjob's environment with a clean environment
+pub const CLEAN_ENV_ALLOW: &[&str] = &[
+    "PATH", "HOME", "USER", "LOGNAME", "SHELL", "TERM", "LANG", "LC_ALL", "LC_CTYPE", "TZ",
+    "TMPDIR",
+];
+
+///time between SIGTERM and SIGKILL when stopping a job
+pub const KILL_GRACE: Duration = Duration::from_secs(5);
+
+static PENDING_SIGNAL: AtomicI32 = AtomicI32::new(0);
+
+extern "C" fn record_signal(sig: libc::c_int) {
+    PENDING_SIGNAL.store(sig, Ordering::SeqCst);
+}
+
+///catch SIGINT and SIGTERM so that they can be forwarded to the jobs
+pub fn catch_signals() {
+    unsafe {
+        libc::signal(
+            libc::SIGINT,
+            record_signal as *const () as libc::sighandler_t,
+        );
+        libc::signal(
+            libc::SIGTERM,
+            record_signal as *const () as libc::sighandler_t,
+        );
+    }
+}
+
+///the last SIGINT or SIGTERM received since the previous call
+pub fn take_signal() -> Option<i32> {
+    match PENDING_SIGNAL.swap(0, Ordering::SeqCst) {
+        0 => None,
+        sig => Some(sig),
+    }
+}
+
+pub fn is_tty(fd: libc::c_int) -> bool {
+    return unsafe { libc::isatty(fd) } == 1;
+}
+
+///hand the terminal to a process group; SIGTTOU is ignored because we may
+///not be the foreground group any more
+fn set_foreground(pgid: libc::pid_t) {
+    unsafe {
+        let old = libc::signal(libc::SIGTTOU, libc::SIG_IGN);
+        libc::tcsetpgrp(libc::STDIN_FILENO, pgid);
+        libc::signal(libc::SIGTTOU, old);
+    }
+}
+
+///parse `90`, `1.5s`, `500ms`, `5m` or `2h`
+pub fn parse_duration(s: &str) -> Option<Duration> {
+    let s = s.trim();
+    let (num, unit) = match s.find(|c: char| !(c.is_ascii_digit() || c == '.')) {
+        Some(i) => (&s[..i], s[i..].trim()),
+        None => (s, "s"),
+    };
+    let num: f64 = num.parse().ok()?;
+    let secs = match unit {
+        "ms" => num / 1000.0,
+        "s" => num,
+        "m" => num * 60.0,
+        "h" => num * 3600.0,
+        _ => return None,
+    };
+    if !secs.is_finite() || secs < 0.0 {
+        return None;
+    }
+    return Some(Duration::from_secs_f64(secs));
+}
+
+///parse a size in bytes like `4096`, `100k`, `512M` or `2GiB`, in powers of 1024
+pub fn parse_size(s: &str) -> Option<u64> {
+    let s = s.trim();
+    let (num, unit) = match s.find(|c: char| !(c.is_ascii_digit() || c == '.')) {
+        Some(i) => (&s[..i], s[i..].trim()),
+        None => (s, ""),
+    };
+    let num: f64 = num.parse().ok()?;
+    let unit = unit.trim_end_matches("iB").trim_end_matches('B');
+    let scale = match unit.to_ascii_uppercase().as_str() {
+        "" => 1u64,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => return None,
+    };
+    let bytes = num * scale as f64;
+    if !bytes.is_finite() || bytes < 0.0 || bytes > u64::MAX as f64 {
+        return None;
+    }
+    return Some(bytes as u64);
+}
+
+///errno of the calling thread, which each libc has its own way to get at
+#[cfg(any(target_os = "linux", target_os = "emscripten"))]
+unsafe fn errno_location() -> *mut libc::c_int {
+    return libc::__errno_location();
+}
+
+#[cfg(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "dragonfly"
+))]
+unsafe fn errno_location() -> *mut libc::c_int {
+    return libc::__error();
+}
+
+#[cfg(any(target_os = "android", target_os = "openbsd", target_os = "netbsd"))]
+unsafe fn errno_location() -> *mut libc::c_int {
+    return libc::__errno();
+}
+
+#[cfg(any(target_os = "solaris", target_os = "illumos"))]
+unsafe fn errno_location() -> *mut libc::c_int {
+    return libc::___errno();
+}
+
+///resource limits and priority of a job, set in the child before it starts
+///the interpreter and inherited by everything it runs
+#[derive(PartialEq, Clone, Debug, Default)]
+pub struct Limits {
+    ///bytes of address space; beyond it allocations fail, which the job
+    ///reports like any other error of its own
+    pub memory: Option<u64>,
+    ///seconds of cpu time, after which the job gets SIGXCPU
+    pub cpu_seconds: Option<u64>,
+    ///largest file the job may write, beyond it gets SIGXFSZ
+    pub file_size: Option<u64>,
+    ///added to the niceness, only root may use a negative value
+    pub nice: Option<i32>,
+}
+
+impl Limits {
+    pub fn is_empty(&self) -> bool {
+        return *self == Limits::default();
+    }
+
+    ///runs in the child between fork and exec, so only async-signal-safe calls
+    fn apply(&self) -> Result<()> {
+        let set = |resource, soft: u64, hard: u64| {
+            let limit = libc::rlimit {
+                rlim_cur: soft as libc::rlim_t,
+                rlim_max: hard as libc::rlim_t,
+            };
+            if unsafe { libc::setrlimit(resource, &limit) } != 0 {
+                return Err(Error::last_os_error());
+            }
+            return Ok(());
+        };
+        if let Some(bytes) = self.memory {
+            set(libc::RLIMIT_AS, bytes, bytes)?;
+        }
+        //the hard limit kills a job that ignores SIGXCPU a little later
+        if let Some(secs) = self.cpu_seconds {
+            set(libc::RLIMIT_CPU, secs, secs + KILL_GRACE.as_secs())?;
+        }
+        if let Some(bytes) = self.file_size {
+            set(libc::RLIMIT_FSIZE, bytes, bytes)?;
+        }
+        if let Some(inc) = self.nice {
+            //nice returns the new niceness, which may be -1, so errno tells failures apart
+            unsafe {
+                *errno_location() = 0;
+                if libc::nice(inc) == -1 {
+                    let e = Error::last_os_error();
+                    if e.raw_os_error() != Some(0) {
+                        return Err(e);
+                    }
+                }
+            }
+        }
+        return Ok(());
+    }
+}
+
+///a spawned script running in its own process group; the temp file is kept
+///alive until the interpreter exits
+pub struct RunningCode {
+    child: Child,
+    pgid: libc::pid_t,
+    foreground: bool,
+    ///none when the script is kept
+    _script: Option<NamedTempFile>,
+    pumps: Vec<JoinHandle<()>>,
+    stdin: Option<ChildStdin>,
+    sink: SharedSink,
+    blocks_done: Receiver<Option<i32>>,
+}
+
+impl RunningCode {
+    pub fn try_wait(&mut self) -> Result<Option<ExitStatus>> {
+        let status = self.child.try_wait()?;
+        if status.is_some() {
+            self.finish();
+        }
+        return Ok(status);
+    }
+
+    #[cfg(test)]
+    pub fn wait(&mut self) -> Result<ExitStatus> {
+        let status = self.child.wait()?;
+        self.finish();
+        return Ok(status);
+    }
+
+    ///send a signal to every process of the job
+    pub fn signal(&self, sig: i32) {
+        unsafe {
+            libc::killpg(self.pgid, sig);
+        }
+    }
+
+    ///feed text to a session started with a session token
+    pub fn write_input(&mut self, text: &str) -> Result<()> {
+        let stdin = match self.stdin.as_mut() {
+            Some(stdin) => stdin,
+            None => return Err(Error::new(ErrorKind::BrokenPipe, "stdin is closed")),
+        };
+        stdin.write_all(text.as_bytes())?;
+        return stdin.flush();
+    }
+
+    ///end of input: a session interpreter exits once it has run what it got
+    pub fn close_input(&mut self) {
+        self.stdin = None;
+    }
+
+    ///send the output from now on somewhere else
+    pub fn set_output(&self, sink: OutputSink) {
+        *self.sink.lock().unwrap() = sink;
+    }
+
+    ///the next sentinel seen on stdout or stderr since the last call, with
+    ///the number that followed it
+    pub fn block_done(&self) -> Option<Option<i32>> {
+        return self.blocks_done.try_recv().ok();
+    }
+
+    ///whether a process of the job is still there, the interpreter or
+    ///whatever it left running
+    pub fn group_alive(&self) -> bool {
+        return unsafe { libc::killpg(self.pgid, 0) } == 0;
+    }
+
+    ///take the terminal back and wait for the output to drain. What the job
+    ///left running in the background keeps running, and keeps the output
+    ///open, so its pumps are left to finish on their own.
+    fn finish(&mut self) {
+        self.stdin = None;
+        if self.foreground {
+            set_foreground(unsafe { libc::getpgrp() });
+            self.foreground = false;
+        }
+        if self.group_alive() {
+            self.pumps.clear();
+        }
+        for pump in self.pumps.drain(..) {
+            pump.join().unwrap();
+        }
+    }
+}
+
+///where the output of a running interpreter goes. A session points it at
+///another task between blocks, so the pumps look it up for every chunk.
+#[derive(Default)]
+pub struct OutputSink {
+    pub prefix: Option<String>,
+    pub stdout_log: Option<File>,
+    pub stderr_log: Option<File>,
+    ///secrets are masked before the output goes anywhere
+    pub stdout_mask: Masker,
+    pub stderr_mask: Masker,
+}
+
+type SharedSink = Arc<Mutex<OutputSink>>;
+
+///write a chunk of output; unless it is whole, like a line, the masker may
+///keep its end for later
+fn write_output(sink: &mut OutputSink, to_stderr: bool, chunk: &[u8], whole: bool) {
+    let (log, mask) = if to_stderr {
+        (sink.stderr_log.as_mut(), &mut sink.stderr_mask)
+    } else {
+        (sink.stdout_log.as_mut(), &mut sink.stdout_mask)
+    };
+    let chunk = if whole {
+        mask.finish(chunk)
+    } else {
+        mask.feed(chunk)
+    };
+    if chunk.is_empty() {
+        return;
+    }
+    if let Some(f) = log {
+        let _ = f.write_all(&chunk);
+    }
+    let mut buf = Vec::new();
+    if let Some(prefix) = &sink.prefix {
+        buf.extend(prefix.as_bytes());
+    }
+    buf.extend(&chunk);
+    if sink.prefix.is_some() && !chunk.ends_with(b"\n") {
+        buf.push(b'\n');
+    }
+    let _ = if to_stderr {
+        stderr().lock().write_all(&buf)
+    } else {
+        let mut out = stdout().lock();
+        out.write_all(&buf).and_then(|_| out.flush())
+    };
+}
+
+///the end of a block in the output of a session: the pump sends the number
+///following the token on the line, if any
+struct Sentinel {
+    token: Vec<u8>,
+    done: Sender<Option<i32>>,
+}
+
+///copy a child pipe to our own stdout or stderr and to the log file, if any.
+///With a prefix it goes line by line and every line starts with the prefix,
+///without it the output is copied as it comes so that prompts show up.
+fn pump_output<R: Read + Send + 'static>(
+    pipe: R,
+    to_stderr: bool,
+    sink: SharedSink,
+    sentinel: Option<Sentinel>,
+) -> JoinHandle<()> {
+    return spawn(move || {
+        let by_line = sentinel.is_some() || sink.lock().unwrap().prefix.is_some();
+        let mut reader = BufReader::new(pipe);
+        let mut chunk = Vec::new();
+        loop {
+            chunk.clear();
+            let read = if by_line {
+                reader.read_until(b'\n', &mut chunk)
+            } else {
+                reader.fill_buf().map(|b| {
+                    chunk.extend_from_slice(b);
+                    return b.len();
+                })
+            };
+            match read {
+                Ok(0) | Err(_) => {
+                    write_output(&mut sink.lock().unwrap(), to_stderr, &[], true);
+                    break;
+                }
+                Ok(n) => {
+                    if !by_line {
+                        reader.consume(n);
+                    }
+                }
+            }
+            let found = sentinel.as_ref().and_then(|s| {
+                let at = chunk
+                    .windows(s.token.len())
+                    .position(|w| w == &s.token[..])?;
+                return Some((s, at));
+            });
+            match found {
+                Some((s, at)) => {
+                    //output of the block that did not end with a newline
+                    if at > 0 {
+                        write_output(&mut sink.lock().unwrap(), to_stderr, &chunk[..at], true);
+                    }
+                    let rest = String::from_utf8_lossy(&chunk[at + s.token.len()..]);
+                    let _ = s.done.send(rest.trim().parse().ok());
+                }
+                None => write_output(&mut sink.lock().unwrap(), to_stderr, &chunk, by_line),
+            }
+        }
+    });
+}
+
+///how to start a script
+#[derive(Clone, Debug, Default)]
+pub struct RunOptions {
+    ///extra environment variables for the interpreter
+    pub env: Vec<(String, String)>,
+    ///pipe the output and tag every line with this, instead of inheriting stdio
+    pub prefix: Option<String>,
+    ///files to copy stdout and stderr to
+    pub log: Option<(PathBuf, PathBuf)>,
+    ///working directory of the interpreter
+    pub dir: Option<PathBuf>,
+    ///start from CLEAN_ENV_ALLOW instead of all of orgjob's environment
+    pub clean_env: bool,
+    ///file to read stdin from instead of inheriting it
+    pub stdin: Option<PathBuf>,
+    pub limits: Limits,
+    ///values replaced with **** in the output
+    pub mask: Vec<String>,
+    ///write the script to this file and leave it there, instead of a temp file
+    pub script: Option<PathBuf>,
+    ///run the interpreter in its trace mode, see trace_args
+    pub trace: bool,
+    ///keep stdin as a pipe for write_input and report every output line
+    ///holding this token through block_done, see session.rs
+    pub session_token: Option<String>,
+}
+
+///write a script that is meant to be looked at or run again by hand
+fn keep_script(path: &Path, code: &str) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, code)?;
+    return fs::set_permissions(path, fs::Permissions::from_mode(0o755));
+}
+
+///start the interpreter on a script holding code, in a new process group.
+///A job with inherited stdio gets the terminal while it runs so that it can
+///read from it and receives Ctrl-C directly.
+pub fn spawn_code<S: AsRef<OsStr>>(
+    interpreter: &str,
+    code: &str,
+    args: &[S],
+    options: &RunOptions,
+) -> Result<RunningCode> {
+    let (script_file, fname) = match &options.script {
+        Some(path) => {
+            keep_script(path, code)?;
+            (None, path.as_os_str().to_owned())
+        }
+        None => {
+            let mut f = NamedTempFile::new()?;
+            f.write_all(code.as_bytes())?;
+            f.flush()?;
+            let fname = f.path().as_os_str().to_owned();
+            (Some(f), fname)
+        }
+    };
+    let mut args_vec = Vec::new();
+    if options.trace {
+        match trace_args(interpreter) {
+            Some(trace) => args_vec.extend(trace.iter().map(OsStr::new)),
+            None => {
+                return Err(Error::new(
+                    ErrorKind::Unsupported,
+                    format!("{} has no trace mode", interpreter),
+                ))
+            }
+        }
+    }
+    args_vec.push(fname.as_os_str());
+    for arg in args {
+        args_vec.push(arg.as_ref());
+    }
+    let mut cmd = Command::new(interpreter);
+    cmd.args(&args_vec);
+    if options.clean_env {
+        cmd.env_clear();
+        for key in CLEAN_ENV_ALLOW {
+            if let Some(value) = std::env::var_os(key) {
+                cmd.env(key, value);
+            }
+        }
+    }
+    cmd.envs(options.env.iter().map(|(k, v)| (k, v)));
+    if let Some(dir) = &options.dir {
+        if !dir.is_dir() {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("working directory {} does not exist", dir.display()),
+            ));
+        }
+        cmd.current_dir(dir);
+    }
+    let prefix = options.prefix.clone();
+    let session = options.session_token.is_some();
+    if session {
+        cmd.stdin(Stdio::piped());
+    } else if let Some(path) = &options.stdin {
+        let file = File::open(path).map_err(|e| {
+            Error::new(
+                e.kind(),
+                format!("cannot read stdin {}: {}", path.display(), e),
+            )
+        })?;
+        cmd.stdin(file);
+    } else if prefix.is_some() {
+        cmd.stdin(Stdio::null());
+    }
+    let masked = !options.mask.is_empty();
+    if prefix.is_some() || options.log.is_some() || session || masked {
+        cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+    }
+    let (stdout_log, stderr_log) = match &options.log {
+        Some((out, err)) => (Some(File::create(out)?), Some(File::create(err)?)),
+        None => (None, None),
+    };
+    let sink = Arc::new(Mutex::new(OutputSink {
+        prefix,
+        stdout_log,
+        stderr_log,
+        stdout_mask: Masker::new(&options.mask),
+        stderr_mask: Masker::new(&options.mask),
+    }));
+    let foreground = options.prefix.is_none()
+        && !session
+        && options.stdin.is_none()
+        && is_tty(libc::STDIN_FILENO);
+    let limits = options.limits.clone();
+    unsafe {
+        cmd.pre_exec(move || {
+            limits.apply()?;
+            libc::setpgid(0, 0);
+            if foreground {
+                set_foreground(libc::getpid());
+            }
+            return Ok(());
+        });
+    }
+    let mut child = match cmd.spawn() {
+        Ok(child) => child,
+        Err(e) if e.kind() == ErrorKind::PermissionDenied && options.limits.nice.is_some() => {
+            return Err(Error::new(e.kind(), format!("cannot apply NICE: {}", e)));
+        }
+        Err(e) => return Err(e),
+    };
+    let pgid = child.id() as libc::pid_t;
+    if foreground {
+        set_foreground(pgid);
+    }
+    let mut pumps = Vec::new();
+    let (done, blocks_done) = channel();
+    let sentinel = || {
+        let token = options.session_token.as_ref()?;
+        return Some(Sentinel {
+            token: token.as_bytes().to_vec(),
+            done: done.clone(),
+        });
+    };
+    if let (Some(out), Some(err)) = (child.stdout.take(), child.stderr.take()) {
+        pumps.push(pump_output(out, false, sink.clone(), sentinel()));
+        pumps.push(pump_output(err, true, sink.clone(), sentinel()));
+    }
+    return Ok(RunningCode {
+        stdin: child.stdin.take(),
+        child,
+        pgid,
+        foreground,
+        _script: script_file,
+        pumps,
+        sink,
+        blocks_done,
+    });
+}
+
+#[cfg(test)]
+pub fn run_code<S: AsRef<OsStr>>(interpreter: &str, code: &str, args: &[S]) -> Result<ExitStatus> {
+    return spawn_code(interpreter, code, args, &RunOptions::default())?.wait();
+}
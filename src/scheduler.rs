@@ -0,0 +1,690 @@
+use super::history::*;
+use super::interpreter::*;
+use super::mask::*;
+use super::runner::*;
+use super::session::*;
+use super::stamp::*;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+///a unit of work for the scheduler; deps are indices of tasks that must succeed first
+pub struct Task {
+    pub name: String,
+    ///full path of the heading, for reports
+    pub path: String,
+    ///heading path and org file line of every code block in code
+    pub origin: Vec<(String, usize)>,
+    pub interpreter: String,
+    pub code: String,
+    pub args: Vec<String>,
+    pub env: Vec<(String, String)>,
+    ///working directory, the current one when none
+    pub dir: Option<PathBuf>,
+    ///names in env whose values must not be written anywhere
+    pub secrets: Vec<String>,
+    ///values replaced with **** in the output and its logs
+    pub mask: Vec<String>,
+    pub deps: Vec<usize>,
+    ///skip the task when its outputs are up to date
+    pub check: Option<BuildCheck>,
+    ///stop the task with SIGTERM, then SIGKILL, when it runs longer
+    pub timeout: Option<Duration>,
+    pub retry: Option<RetryPolicy>,
+    ///what has to be typed before the task may run
+    pub confirm: Option<String>,
+    ///run blocks in the long-lived interpreter of this session instead of code
+    pub session: Option<String>,
+    ///the code blocks for a session, with an id telling them apart across tasks
+    pub blocks: Vec<(String, String)>,
+    ///where stdin comes from, orgjob's own stdin when none
+    pub stdin: Option<TaskInput>,
+    ///variables set to the stdout of earlier tasks, without the final newlines
+    pub inputs: Vec<(String, usize)>,
+    pub limits: Limits,
+    ///parameters a dry run left without a value
+    pub unbound: Vec<String>,
+}
+
+///what a task reads on stdin
+#[derive(PartialEq, Clone, Debug)]
+pub enum TaskInput {
+    ///the stdout of an earlier task
+    Output(usize),
+    File(PathBuf),
+    ///the text of a named example block
+    Example {
+        name: String,
+        text: String,
+    },
+}
+
+///how often and when to run a failed task again
+#[derive(PartialEq, Clone, Debug)]
+pub struct RetryPolicy {
+    pub retries: u32,
+    pub delay: Duration,
+    ///the delay is multiplied by this after every attempt
+    pub backoff: f64,
+    ///exit codes worth another attempt, any failure when empty
+    pub exit_codes: Vec<i32>,
+}
+
+impl RetryPolicy {
+    fn should_retry(&self, status: &TaskStatus, attempt: u32) -> bool {
+        if attempt > self.retries {
+            return false;
+        }
+        return match status {
+            TaskStatus::Exited(0) => false,
+            TaskStatus::Exited(code) => {
+                self.exit_codes.is_empty() || self.exit_codes.contains(code)
+            }
+            TaskStatus::TimedOut(_) => self.exit_codes.is_empty(),
+            _ => false,
+        };
+    }
+
+    ///delay before the attempt after `attempt`, counting from 1
+    pub fn delay_after(&self, attempt: u32) -> Duration {
+        return self.delay.mul_f64(self.backoff.powi(attempt as i32 - 1));
+    }
+}
+
+pub struct ScheduleOptions {
+    pub jobs: usize,
+    pub fail_fast: bool,
+    ///tag every output line with the task name instead of inheriting stdio
+    pub prefix_output: bool,
+    ///record the script, output and status of every task here
+    pub log: Option<RunLog>,
+    ///pass only the variables in CLEAN_ENV_ALLOW and those of the task
+    pub clean_env: bool,
+    ///keep the script of every task in this directory, named after the task
+    pub keep_scripts: Option<PathBuf>,
+    ///run the interpreters in their trace mode
+    pub trace: bool,
+    ///with --terminal, a task that runs alone keeps the terminal for its
+    ///output, which the run log then does not have
+    pub terminal: bool,
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub enum TaskStatus {
+    Pending,
+    Running,
+    Exited(i32),
+    UpToDate,
+    TimedOut(Duration),
+    Killed(i32),
+    LimitExceeded(ExceededLimit),
+    Skipped,
+    ///not started because a signal stopped the run
+    Interrupted(i32),
+    Error(String),
+}
+
+///a resource limit that stops a job with a signal of its own when it is exceeded
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum ExceededLimit {
+    CpuTime,
+    FileSize,
+}
+
+impl ExceededLimit {
+    pub fn signal(self) -> i32 {
+        return match self {
+            ExceededLimit::CpuTime => libc::SIGXCPU,
+            ExceededLimit::FileSize => libc::SIGXFSZ,
+        };
+    }
+}
+
+impl TaskStatus {
+    pub fn is_success(&self) -> bool {
+        return matches!(self, TaskStatus::Exited(0) | TaskStatus::UpToDate);
+    }
+    fn is_finished(&self) -> bool {
+        return !matches!(self, TaskStatus::Pending | TaskStatus::Running);
+    }
+}
+
+impl fmt::Display for TaskStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TaskStatus::Pending => write!(f, "pending"),
+            TaskStatus::Running => write!(f, "running"),
+            TaskStatus::Exited(0) => write!(f, "ok"),
+            TaskStatus::Exited(code) => write!(f, "failed with exit code {}", code),
+            TaskStatus::UpToDate => write!(f, "up to date"),
+            TaskStatus::TimedOut(d) => write!(f, "timed out after {:?}", d),
+            TaskStatus::Killed(sig) => write!(f, "killed by signal {}", sig),
+            TaskStatus::LimitExceeded(ExceededLimit::CpuTime) => {
+                write!(f, "stopped for exceeding MAX_CPU_SECONDS")
+            }
+            TaskStatus::LimitExceeded(ExceededLimit::FileSize) => {
+                write!(f, "stopped for exceeding MAX_FILE_SIZE")
+            }
+            TaskStatus::Skipped => write!(f, "skipped"),
+            TaskStatus::Interrupted(sig) => {
+                write!(f, "skipped, the run was stopped by signal {}", sig)
+            }
+            TaskStatus::Error(e) => write!(f, "error: {}", e),
+        }
+    }
+}
+
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+///interpreter and name of a session
+type SessionKey = (String, String);
+
+///what a running task is waiting for
+enum Process {
+    Script(RunningCode),
+    Session(SessionKey),
+}
+
+struct RunningTask {
+    index: usize,
+    process: Process,
+    started: Instant,
+    deadline: Option<Instant>,
+    ///when SIGTERM was sent, to escalate to SIGKILL after the grace period
+    terminated: Option<Instant>,
+    timed_out: bool,
+}
+
+impl RunningTask {
+    fn signal(&self, sessions: &HashMap<SessionKey, Session>, sig: i32) {
+        match &self.process {
+            Process::Script(code) => code.signal(sig),
+            Process::Session(key) => sessions[key].signal(sig),
+        }
+    }
+
+    fn terminate(&mut self, sessions: &HashMap<SessionKey, Session>) {
+        if self.terminated.is_none() {
+            self.signal(sessions, libc::SIGTERM);
+            self.terminated = Some(Instant::now());
+        }
+    }
+
+    fn session(&self) -> Option<&SessionKey> {
+        return match &self.process {
+            Process::Script(_) => None,
+            Process::Session(key) => Some(key),
+        };
+    }
+}
+
+pub fn exit_status(s: std::process::ExitStatus) -> TaskStatus {
+    use std::os::unix::process::ExitStatusExt;
+    return match (s.code(), s.signal()) {
+        (Some(code), _) => TaskStatus::Exited(code),
+        (None, Some(sig)) => TaskStatus::Killed(sig),
+        (None, None) => TaskStatus::Error("unknown exit status".to_string()),
+    };
+}
+
+///a job killed by SIGXCPU or SIGXFSZ went over its limit, and so did a
+///shell whose command was, exiting with 128 plus the signal
+fn limit_status(status: TaskStatus, limits: &Limits) -> TaskStatus {
+    let sig = match status {
+        TaskStatus::Killed(sig) => sig,
+        TaskStatus::Exited(code) if code > 128 => code - 128,
+        _ => return status,
+    };
+    if sig == libc::SIGXCPU && limits.cpu_seconds.is_some() {
+        return TaskStatus::LimitExceeded(ExceededLimit::CpuTime);
+    }
+    if sig == libc::SIGXFSZ && limits.file_size.is_some() {
+        return TaskStatus::LimitExceeded(ExceededLimit::FileSize);
+    }
+    return status;
+}
+
+///how a task ended, how long its attempts ran in total and how many there were
+#[derive(PartialEq, Clone, Debug)]
+pub struct TaskRun {
+    pub status: TaskStatus,
+    pub duration: Duration,
+    pub attempts: u32,
+}
+
+///schedule_tasks for callers that only need the statuses
+#[cfg(test)]
+pub fn run_tasks(tasks: &[Task], options: &ScheduleOptions) -> Vec<TaskStatus> {
+    return schedule_tasks(tasks, options)
+        .into_iter()
+        .map(|r| r.status)
+        .collect();
+}
+
+///run tasks with at most `jobs` of them at the same time, starting each once
+///all of its dependencies succeeded. Every task runs in its own process group;
+///SIGINT and SIGTERM received by orgjob are forwarded to all running tasks and
+///stop the run.
+///With fail_fast the first failure stops the running tasks and skips the rest.
+///A task with a retry policy runs again after its delay instead of failing,
+///until it succeeds or runs out of attempts.
+pub fn schedule_tasks(tasks: &[Task], options: &ScheduleOptions) -> Vec<TaskRun> {
+    let jobs = options.jobs;
+    let fail_fast = options.fail_fast;
+    assert!(jobs > 0);
+    let mut status = vec![TaskStatus::Pending; tasks.len()];
+    let mut running: Vec<RunningTask> = Vec::new();
+    //stopped tasks whose interpreter exited while the rest of the group did
+    //not, with when SIGTERM was sent
+    let mut stopping: Vec<(RunningCode, Instant)> = Vec::new();
+    let mut aborted = false;
+    //the signal that stopped the run
+    let mut signalled = None;
+    let mut attempts = vec![0u32; tasks.len()];
+    let mut durations = vec![Duration::from_secs(0); tasks.len()];
+    //failed tasks waiting for their next attempt, and when it may start
+    let mut retry_at: Vec<Option<(Instant, TaskStatus)>> = vec![None; tasks.len()];
+    let mut sessions: HashMap<SessionKey, Session> = HashMap::new();
+    //stdout of the tasks that others read, kept in the run log or else here
+    let mut outputs: Vec<Option<PathBuf>> = vec![None; tasks.len()];
+    let mut capture_dir: Option<tempfile::TempDir> = None;
+    let read_by_others = |i: usize| {
+        tasks
+            .iter()
+            .any(|t| t.stdin == Some(TaskInput::Output(i)) || t.inputs.iter().any(|(_, j)| *j == i))
+    };
+    catch_signals();
+
+    loop {
+        if let Some(sig) = take_signal() {
+            for r in running.iter_mut() {
+                r.signal(&sessions, sig);
+                r.terminated.get_or_insert_with(Instant::now);
+            }
+            for (code, _) in stopping.iter() {
+                code.signal(sig);
+            }
+            aborted = true;
+            signalled = Some(sig);
+        }
+
+        //skip tasks that can never start
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for i in 0..tasks.len() {
+                if status[i] == TaskStatus::Pending
+                    && (aborted
+                        || tasks[i]
+                            .deps
+                            .iter()
+                            .any(|d| status[*d].is_finished() && !status[*d].is_success()))
+                {
+                    status[i] = match retry_at[i].take() {
+                        Some((_, failed)) => failed,
+                        None => match signalled {
+                            Some(sig) => TaskStatus::Interrupted(sig),
+                            None => TaskStatus::Skipped,
+                        },
+                    };
+                    changed = true;
+                }
+            }
+        }
+
+        for i in 0..tasks.len() {
+            if running.len() >= jobs {
+                break;
+            }
+            if status[i] != TaskStatus::Pending
+                || !tasks[i].deps.iter().all(|d| status[*d].is_success())
+            {
+                continue;
+            }
+            if retry_at[i]
+                .as_ref()
+                .is_some_and(|(at, _)| Instant::now() < *at)
+            {
+                continue;
+            }
+            let t = &tasks[i];
+            let key = t
+                .session
+                .as_ref()
+                .map(|s| (t.interpreter.clone(), s.clone()));
+            //a session runs the blocks of one task at a time
+            if key.is_some() && running.iter().any(|r| r.session() == key.as_ref()) {
+                continue;
+            }
+            retry_at[i] = None;
+            if let Some(check) = &t.check {
+                if check.check() == Freshness::UpToDate {
+                    println!(
+                        "{}: up to date, outputs are newer than sources and the code is unchanged",
+                        t.name
+                    );
+                    status[i] = TaskStatus::UpToDate;
+                    continue;
+                }
+            }
+            //neither prefixed, masked, in a session nor read by another task
+            let to_terminal = options.terminal
+                && !options.prefix_output
+                && key.is_none()
+                && t.mask.is_empty()
+                && !read_by_others(i);
+            let log = match &options.log {
+                Some(log) => match log.start_task(i, t, attempts[i] + 1) {
+                    Ok(_) if to_terminal => {
+                        if let Err(e) = log.note_terminal(i, t, attempts[i] + 1) {
+                            println!("failed to write the run log of {}: {}", t.name, e);
+                        }
+                        None
+                    }
+                    Ok(files) => Some(files),
+                    Err(e) => {
+                        println!("failed to write the run log of {}: {}", t.name, e);
+                        None
+                    }
+                },
+                None => None,
+            };
+            let log = match (log, read_by_others(i)) {
+                (None, true) => {
+                    if capture_dir.is_none() {
+                        capture_dir = tempfile::tempdir().ok();
+                    }
+                    capture_dir.as_ref().map(|d| {
+                        let name = format!("{:02}-{}", i + 1, safe_file_name(&t.name));
+                        (
+                            d.path().join(format!("{}.stdout", name)),
+                            d.path().join(format!("{}.stderr", name)),
+                        )
+                    })
+                }
+                (log, _) => log,
+            };
+            if read_by_others(i) {
+                outputs[i] = log.as_ref().map(|(out, _)| out.clone());
+            }
+            let mut env = t.env.clone();
+            for (name, j) in t.inputs.iter() {
+                let value = match &outputs[*j] {
+                    Some(path) => std::fs::read_to_string(path).unwrap_or_default(),
+                    None => String::new(),
+                };
+                env.push((name.clone(), value.trim_end_matches('\n').to_string()));
+            }
+            //the text of an example is handed over in a file that lives until the spawn
+            let mut example_file = None;
+            let stdin = match &t.stdin {
+                //a task that did not run, because it was up to date, gives no input
+                Some(TaskInput::Output(j)) => Some(
+                    outputs[*j]
+                        .clone()
+                        .unwrap_or_else(|| PathBuf::from("/dev/null")),
+                ),
+                Some(TaskInput::File(path)) => Some(path.clone()),
+                Some(TaskInput::Example { text, .. }) => {
+                    match tempfile::NamedTempFile::new()
+                        .and_then(|mut f| f.write_all(text.as_bytes()).map(|_| f))
+                    {
+                        Ok(f) => {
+                            let path = f.path().to_path_buf();
+                            example_file = Some(f);
+                            Some(path)
+                        }
+                        Err(e) => {
+                            status[i] = TaskStatus::Error(e.to_string());
+                            aborted |= fail_fast;
+                            continue;
+                        }
+                    }
+                }
+                None => None,
+            };
+            //the blocks of a session are not scripts of their own
+            let script = match (&options.keep_scripts, &key) {
+                (Some(dir), None) => {
+                    let name = format!(
+                        "{:02}-{}.{}",
+                        i + 1,
+                        safe_file_name(&t.name),
+                        file_extension(&t.interpreter)
+                    );
+                    Some(dir.join(name))
+                }
+                _ => None,
+            };
+            if let (Some(path), 1) = (&script, attempts[i] + 1) {
+                println!("{}: script kept in {}", t.name, path.display());
+            }
+            let run_options = RunOptions {
+                env,
+                prefix: if options.prefix_output {
+                    Some(format!("[{}] ", t.name))
+                } else {
+                    None
+                },
+                log,
+                dir: t.dir.clone(),
+                clean_env: options.clean_env,
+                stdin,
+                limits: t.limits.clone(),
+                mask: t.mask.clone(),
+                script,
+                trace: options.trace && key.is_none(),
+                session_token: None,
+            };
+            let process = match key {
+                Some(key) => start_session_task(&mut sessions, key, t, &run_options),
+                None => {
+                    spawn_code(&t.interpreter, &t.code, &t.args, &run_options).map(Process::Script)
+                }
+            };
+            drop(example_file);
+            match process {
+                Ok(process) => {
+                    status[i] = TaskStatus::Running;
+                    attempts[i] += 1;
+                    running.push(RunningTask {
+                        index: i,
+                        process,
+                        started: Instant::now(),
+                        deadline: t.timeout.map(|d| Instant::now() + d),
+                        terminated: None,
+                        timed_out: false,
+                    });
+                }
+                Err(e) => {
+                    status[i] = TaskStatus::Error(e.to_string());
+                    aborted |= fail_fast;
+                }
+            }
+        }
+
+        stopping.retain(|(code, terminated)| {
+            if !code.group_alive() {
+                return false;
+            }
+            if terminated.elapsed() < KILL_GRACE {
+                return true;
+            }
+            code.signal(libc::SIGKILL);
+            return false;
+        });
+
+        if running.is_empty() && stopping.is_empty() && retry_at.iter().all(|r| r.is_none()) {
+            break;
+        }
+
+        let mut k = 0;
+        while k < running.len() {
+            let r = &mut running[k];
+            let t = &tasks[r.index];
+            let polled = match &mut r.process {
+                Process::Script(code) => code.try_wait().map(|s| s.map(exit_status)),
+                Process::Session(key) => {
+                    let session = sessions.get_mut(key).unwrap();
+                    let polled = session.poll();
+                    //a session that broke or exited starts again for the next task
+                    if polled.is_err() || !session.is_alive() {
+                        sessions.remove(key).unwrap().close();
+                    }
+                    polled
+                }
+            };
+            let done = match polled {
+                Ok(Some(_)) if r.timed_out => Some(TaskStatus::TimedOut(t.timeout.unwrap())),
+                Ok(Some(s)) => Some(limit_status(s, &t.limits)),
+                Ok(None) => None,
+                Err(e) => Some(TaskStatus::Error(e.to_string())),
+            };
+            match done {
+                Some(s) => {
+                    let r = running.remove(k);
+                    if let (Process::Script(code), Some(terminated)) = (r.process, r.terminated) {
+                        if code.group_alive() {
+                            stopping.push((code, terminated));
+                        }
+                    }
+                    let i = r.index;
+                    let took = r.started.elapsed();
+                    durations[i] += took;
+                    if let Some(log) = &options.log {
+                        if let Err(e) = log.record_attempt(i, t, attempts[i], &s, took) {
+                            println!("failed to write the run log of {}: {}", t.name, e);
+                        }
+                    }
+                    //tasks stopped because the run is over are not retried
+                    let stopped = aborted || (r.terminated.is_some() && !r.timed_out);
+                    let retry = t
+                        .retry
+                        .as_ref()
+                        .filter(|p| !stopped && p.should_retry(&s, attempts[i]));
+                    if let Some(policy) = retry {
+                        let delay = policy.delay_after(attempts[i]);
+                        println!(
+                            "{}: attempt {} of {} {}, retrying in {:?}",
+                            t.name,
+                            attempts[i],
+                            policy.retries + 1,
+                            s,
+                            delay
+                        );
+                        retry_at[i] = Some((Instant::now() + delay, s));
+                        status[i] = TaskStatus::Pending;
+                        continue;
+                    }
+                    if attempts[i] > 1 {
+                        println!("{}: attempt {} {}", t.name, attempts[i], s);
+                    }
+                    aborted |= fail_fast && !s.is_success();
+                    if let (true, Some(check)) = (s.is_success(), &t.check) {
+                        if let Err(e) = check.record() {
+                            println!("failed to record {}: {}", check.stamp.display(), e);
+                        }
+                    }
+                    status[r.index] = s;
+                }
+                None => {
+                    let now = Instant::now();
+                    if r.deadline.is_some_and(|d| now >= d) && r.terminated.is_none() {
+                        r.timed_out = true;
+                        r.terminate(&sessions);
+                    }
+                    if r.terminated.is_some_and(|t| now >= t + KILL_GRACE) {
+                        r.signal(&sessions, libc::SIGKILL);
+                    }
+                    k += 1;
+                }
+            }
+        }
+
+        if aborted {
+            for r in running.iter_mut() {
+                r.terminate(&sessions);
+            }
+        }
+        sleep(POLL_INTERVAL);
+    }
+    for (_, session) in sessions.drain() {
+        session.close();
+    }
+    return status
+        .into_iter()
+        .zip(durations.into_iter().zip(attempts))
+        .map(|(status, (duration, attempts))| TaskRun {
+            status,
+            duration,
+            attempts,
+        })
+        .collect();
+}
+
+///hand a task to its session, starting the session for the first task
+fn start_session_task(
+    sessions: &mut HashMap<SessionKey, Session>,
+    key: SessionKey,
+    task: &Task,
+    options: &RunOptions,
+) -> std::io::Result<Process> {
+    if !sessions.contains_key(&key) {
+        let session_options = RunOptions {
+            prefix: None,
+            log: None,
+            ..options.clone()
+        };
+        sessions.insert(
+            key.clone(),
+            Session::start(&task.interpreter, &session_options)?,
+        );
+    }
+    let sink = OutputSink {
+        prefix: options.prefix.clone(),
+        stdout_log: options
+            .log
+            .as_ref()
+            .map(|(out, _)| File::create(out))
+            .transpose()?,
+        stderr_log: options
+            .log
+            .as_ref()
+            .map(|(_, err)| File::create(err))
+            .transpose()?,
+        stdout_mask: Masker::new(&options.mask),
+        stderr_mask: Masker::new(&options.mask),
+    };
+    if let Err(e) = sessions.get_mut(&key).unwrap().run_task(task, sink) {
+        sessions.remove(&key).unwrap().close();
+        return Err(e);
+    }
+    return Ok(Process::Session(key));
+}
+
+///exit code for a whole run: 0 when every task succeeded, otherwise that of
+///the first failed task. A timeout gives 124 and a signal 128 plus its number;
+///tasks killed because of another failure, and those a signal kept from
+///starting, only count when nothing else failed.
+pub fn aggregate_status(status: &[TaskStatus]) -> i32 {
+    let mut result = 0;
+    for s in status {
+        match s {
+            TaskStatus::Exited(0) | TaskStatus::UpToDate | TaskStatus::Skipped => {}
+            TaskStatus::Exited(code) => return *code,
+            TaskStatus::TimedOut(_) => return 124,
+            TaskStatus::LimitExceeded(limit) => return 128 + limit.signal(),
+            TaskStatus::Killed(sig) | TaskStatus::Interrupted(sig) => {
+                if result == 0 {
+                    result = 128 + sig;
+                }
+            }
+            _ => return 1,
+        }
+    }
+    return result;
+}
@@ -0,0 +1,260 @@
+use super::export::quote;
+use super::interpreter::*;
+use super::runner::*;
+use super::scheduler::*;
+use std::collections::VecDeque;
+use std::io::{Error, ErrorKind, Result, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+use tempfile::NamedTempFile;
+
+static SESSION_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+//every block is a file that the shell sources; after it the shell prints
+//the token and the status on stdout and the token alone on stderr, so that
+//both pipes are drained up to the end of the block
+const SHELL_DRIVER: &str = r#"__orgjob_done() {
+    printf '%s %s\n' 'TOKEN' "$1"
+    printf '%s\n' 'TOKEN' >&2
+}
+while IFS= read -r __orgjob_line; do
+    eval "$__orgjob_line"
+done
+"#;
+
+//the same with python: one path per line, executed in shared globals
+const PYTHON_DRIVER: &str = r#"import os as _orgjob_os, sys as _orgjob_sys, traceback as _orgjob_tb
+def _orgjob_session():
+    proto = _orgjob_sys.stdin
+    _orgjob_sys.stdin = open(_orgjob_os.devnull)
+    scope = {"__name__": "__main__", "__builtins__": __builtins__}
+    while True:
+        path = proto.readline().rstrip("\n")
+        if not path:
+            break
+        status = 0
+        try:
+            with open(path) as f:
+                code = compile(f.read(), path, "exec")
+            exec(code, scope)
+        except KeyboardInterrupt:
+            raise
+        except SystemExit as e:
+            if isinstance(e.code, int):
+                status = e.code
+            elif e.code is not None:
+                _orgjob_sys.stderr.write("%s\n" % e.code)
+                status = 1
+        except BaseException:
+            _orgjob_tb.print_exc()
+            status = 1
+        _orgjob_sys.stdout.write("TOKEN %d\n" % status)
+        _orgjob_sys.stdout.flush()
+        _orgjob_sys.stderr.write("TOKEN\n")
+        _orgjob_sys.stderr.flush()
+_orgjob_session()
+"#;
+
+///code setting the environment, arguments and working directory of a task
+///in a session that may have been started by another one
+fn task_prelude(style: SessionStyle, task: &Task) -> String {
+    let mut code = String::new();
+    match style {
+        SessionStyle::Shell => {
+            for (k, v) in task.env.iter() {
+                code.push_str(&format!("export {}={}\n", k, quote(v)));
+            }
+            let args: Vec<String> = task.args.iter().map(|a| quote(a)).collect();
+            code.push_str(&format!("set -- {}\n", args.join(" ")));
+            if let Some(dir) = &task.dir {
+                code.push_str(&format!("cd {}\n", quote(&dir.to_string_lossy())));
+            }
+        }
+        SessionStyle::Python => {
+            code.push_str("import os as _orgjob_os, sys as _orgjob_sys\n");
+            for (k, v) in task.env.iter() {
+                code.push_str(&format!(
+                    "_orgjob_os.environ[{}] = {}\n",
+                    py_quote(k),
+                    py_quote(v)
+                ));
+            }
+            let mut argv = vec![py_quote(&task.name)];
+            argv.extend(task.args.iter().map(|a| py_quote(a)));
+            code.push_str(&format!("_orgjob_sys.argv = [{}]\n", argv.join(", ")));
+            if let Some(dir) = &task.dir {
+                code.push_str(&format!(
+                    "_orgjob_os.chdir({})\n",
+                    py_quote(&dir.to_string_lossy())
+                ));
+            }
+        }
+    }
+    return code;
+}
+
+///a long-lived interpreter that runs the blocks of every task with the same
+///`:session` one at a time, so that variables set by one are seen by the next.
+///A block that ran without error is not run again by a later task.
+pub struct Session {
+    code: RunningCode,
+    style: SessionStyle,
+    ///ids of the blocks that succeeded
+    done: Vec<String>,
+    ///blocks of the current task waiting for their turn
+    queue: VecDeque<(String, String)>,
+    ///the block being run, its file, its status and how many sentinels arrived
+    current: Option<(String, NamedTempFile, Option<i32>, u32)>,
+    ///how the current task ended when it is over before the next poll
+    result: Option<TaskStatus>,
+    exited: bool,
+}
+
+impl Session {
+    pub fn start(interpreter: &str, options: &RunOptions) -> Result<Session> {
+        let style = match session_style(interpreter) {
+            Some(style) => style,
+            None => {
+                return Err(Error::new(
+                    ErrorKind::Unsupported,
+                    format!("{} does not support sessions", interpreter),
+                ))
+            }
+        };
+        let token = format!(
+            "__orgjob_{}_{}_block_done__",
+            std::process::id(),
+            SESSION_COUNT.fetch_add(1, Ordering::SeqCst)
+        );
+        let driver = match style {
+            SessionStyle::Shell => SHELL_DRIVER,
+            SessionStyle::Python => PYTHON_DRIVER,
+        };
+        let mut options = options.clone();
+        options.session_token = Some(token.clone());
+        if style == SessionStyle::Python {
+            options
+                .env
+                .push(("PYTHONUNBUFFERED".to_string(), "1".to_string()));
+        }
+        let no_args: &[&str] = &[];
+        let code = spawn_code(
+            interpreter,
+            &driver.replace("TOKEN", &token),
+            no_args,
+            &options,
+        )?;
+        return Ok(Session {
+            code,
+            style,
+            done: Vec::new(),
+            queue: VecDeque::new(),
+            current: None,
+            result: None,
+            exited: false,
+        });
+    }
+
+    ///start running the blocks of a task that did not run yet, after setting
+    ///its environment, with the output going to sink
+    pub fn run_task(&mut self, task: &Task, sink: OutputSink) -> Result<()> {
+        self.code.set_output(sink);
+        self.result = None;
+        self.queue.clear();
+        self.queue
+            .push_back((String::new(), task_prelude(self.style, task)));
+        for (id, code) in task.blocks.iter() {
+            if !self.done.contains(id) {
+                self.queue.push_back((id.clone(), code.clone()));
+            }
+        }
+        if !self.feed_next()? {
+            self.result = Some(TaskStatus::Exited(0));
+        }
+        return Ok(());
+    }
+
+    ///hand the next block to the interpreter, false when there is none
+    fn feed_next(&mut self) -> Result<bool> {
+        let (id, code) = match self.queue.pop_front() {
+            Some(block) => block,
+            None => return Ok(false),
+        };
+        let mut file = NamedTempFile::new()?;
+        file.write_all(code.as_bytes())?;
+        file.flush()?;
+        let path = file.path().to_string_lossy().to_string();
+        let line = match self.style {
+            SessionStyle::Shell => format!(". {} </dev/null; __orgjob_done $?\n", quote(&path)),
+            SessionStyle::Python => format!("{}\n", path),
+        };
+        self.current = Some((id, file, None, 0));
+        self.code.write_input(&line)?;
+        return Ok(true);
+    }
+
+    ///how the current task ended, none while it is running. The task fails
+    ///with the status of its first failing block; when the interpreter exits
+    ///the task gets its exit status and the session is over.
+    pub fn poll(&mut self) -> Result<Option<TaskStatus>> {
+        if let Some(status) = self.result.take() {
+            return Ok(Some(status));
+        }
+        while let Some(status) = self.code.block_done() {
+            let current = match self.current.as_mut() {
+                Some(current) => current,
+                None => continue,
+            };
+            current.2 = current.2.or(status);
+            current.3 += 1;
+            if current.3 < 2 {
+                continue;
+            }
+            let (id, _, status, _) = self.current.take().unwrap();
+            let status = status.unwrap_or(1);
+            if status != 0 {
+                self.queue.clear();
+                return Ok(Some(TaskStatus::Exited(status)));
+            }
+            if !id.is_empty() {
+                self.done.push(id);
+            }
+            if !self.feed_next()? {
+                return Ok(Some(TaskStatus::Exited(0)));
+            }
+        }
+        if let Some(s) = self.code.try_wait()? {
+            self.exited = true;
+            self.current = None;
+            return Ok(Some(exit_status(s)));
+        }
+        return Ok(None);
+    }
+
+    pub fn is_alive(&self) -> bool {
+        return !self.exited;
+    }
+
+    pub fn signal(&self, sig: i32) {
+        self.code.signal(sig);
+    }
+
+    ///let the interpreter exit at the end of its input, killing it when it
+    ///does not within the grace period
+    pub fn close(mut self) {
+        if self.exited {
+            return;
+        }
+        self.code.close_input();
+        let start = Instant::now();
+        let mut killed = false;
+        while let Ok(None) = self.code.try_wait() {
+            if !killed && start.elapsed() >= KILL_GRACE {
+                self.code.signal(libc::SIGKILL);
+                killed = true;
+            }
+            sleep(Duration::from_millis(10));
+        }
+    }
+}
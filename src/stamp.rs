@@ -0,0 +1,111 @@
+use super::code_doc::*;
+use std::fs;
+use std::io::Result;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+///make-style up-to-date check of a job against its declared files
+pub struct BuildCheck {
+    pub creates: Vec<PathBuf>,
+    pub sources: Vec<PathBuf>,
+    pub stamp: PathBuf,
+    pub hash: String,
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub enum Freshness {
+    UpToDate,
+    Stale(String),
+}
+
+fn fnv1a(hash: u64, data: &[u8]) -> u64 {
+    let mut hash = hash;
+    for b in data {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(0x100_0000_01b3);
+    }
+    return hash;
+}
+
+///hash of everything that decides what a run does: the interpreter, the code and the arguments
+pub fn code_hash<S: AsRef<str>>(code: &RunnableCode, args: &[S]) -> String {
+    let mut hash = 0xcbf2_9ce4_8422_2325;
+    hash = fnv1a(hash, code.interpreter.as_bytes());
+    for c in code.code.iter() {
+        hash = fnv1a(hash, b"\0");
+        hash = fnv1a(hash, c.as_bytes());
+    }
+    for a in args {
+        hash = fnv1a(hash, b"\0");
+        hash = fnv1a(hash, a.as_ref().as_bytes());
+    }
+    return format!("{:016x}", hash);
+}
+
+///replace everything but letters, digits, `.`, `-` and `_` so that name can be a file name
+pub fn safe_file_name(name: &str) -> String {
+    return name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+}
+
+///stamps live in .orgjob/stamps next to the org file, one file per job
+pub fn stamp_path(org_file: &Path, fullname: &[String], sep: &str) -> PathBuf {
+    let name = safe_file_name(&fullname.join(sep));
+    let dir = org_file.parent().unwrap_or_else(|| Path::new(""));
+    return dir.join(".orgjob").join("stamps").join(name);
+}
+
+pub fn mtime(path: &Path) -> Option<SystemTime> {
+    return fs::metadata(path).and_then(|m| m.modified()).ok();
+}
+
+impl BuildCheck {
+    pub fn check(&self) -> Freshness {
+        let mut oldest_output = None;
+        for p in self.creates.iter() {
+            match mtime(p) {
+                Some(t) => {
+                    if oldest_output.is_none_or(|o| t < o) {
+                        oldest_output = Some(t);
+                    }
+                }
+                None => return Freshness::Stale(format!("{} does not exist", p.display())),
+            }
+        }
+        let oldest_output = match oldest_output {
+            Some(t) => t,
+            None => return Freshness::Stale("no outputs declared".to_string()),
+        };
+        for p in self.sources.iter() {
+            match mtime(p) {
+                Some(t) => {
+                    if t > oldest_output {
+                        return Freshness::Stale(format!("{} is newer than outputs", p.display()));
+                    }
+                }
+                None => return Freshness::Stale(format!("{} does not exist", p.display())),
+            }
+        }
+        match fs::read_to_string(&self.stamp) {
+            Ok(s) if s.trim() == self.hash => return Freshness::UpToDate,
+            Ok(_) => return Freshness::Stale("code changed since last run".to_string()),
+            Err(_) => return Freshness::Stale("no record of a previous run".to_string()),
+        }
+    }
+
+    ///remember the code hash after a successful run
+    pub fn record(&self) -> Result<()> {
+        if let Some(dir) = self.stamp.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        return fs::write(&self.stamp, format!("{}\n", self.hash));
+    }
+}
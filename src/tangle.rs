@@ -0,0 +1,117 @@
+use super::code_doc::*;
+use super::interpreter::*;
+use std::fs;
+use std::io::Result;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+///content for one :tangle target, gathered from all blocks that name it
+pub struct TangledFile {
+    pub path: PathBuf,
+    pub content: String,
+    pub mode: Option<u32>,
+    pub mkdirp: bool,
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub enum TangleError {
+    InvalidMode { target: String, value: String },
+}
+
+///`(identifier #o755)`, `#o755`, `o755` and `755` are all accepted as octal modes
+fn parse_mode(value: &str) -> Option<u32> {
+    let v = value.trim_matches(|c| c == '(' || c == ')' || c == ' ');
+    let v = v.strip_prefix("identifier").unwrap_or(v).trim();
+    let v = v.strip_prefix('#').unwrap_or(v);
+    let v = v.strip_prefix('o').unwrap_or(v);
+    return u32::from_str_radix(v, 8).ok();
+}
+
+fn is_yes(value: Option<&str>) -> bool {
+    return matches!(value, Some("yes") | Some("t"));
+}
+
+///collect the :tangle targets of the given nodes in document order; relative
+///targets are resolved against the directory of the org file
+pub fn collect_tangled_files(
+    doc: &CodeDoc,
+    nodes: &[DocNodeId],
+    org_file: &Path,
+) -> std::result::Result<Vec<TangledFile>, TangleError> {
+    let dir = org_file.parent().unwrap_or_else(|| Path::new(""));
+    let stem = org_file
+        .file_stem()
+        .map(|x| x.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let mut files: Vec<TangledFile> = Vec::new();
+    for n in nodes {
+        for cb in doc.get_node(*n).code_blocks() {
+            let target = match cb.header_arg("tangle") {
+                None | Some("") | Some("no") => continue,
+                Some("yes") => format!("{}.{}", stem, file_extension(&cb.interpreter)),
+                Some(t) => t.trim_matches('"').to_string(),
+            };
+            let path = dir.join(&target);
+            let i = match files.iter().position(|f| f.path == path) {
+                Some(i) => i,
+                None => {
+                    files.push(TangledFile {
+                        path,
+                        content: String::new(),
+                        mode: None,
+                        mkdirp: false,
+                    });
+                    files.len() - 1
+                }
+            };
+            let file = &mut files[i];
+            if let Some(shebang) = cb.header_arg("shebang") {
+                if file.content.is_empty() {
+                    file.content.push_str(shebang.trim_matches('"'));
+                    file.content.push('\n');
+                    file.mode = file.mode.or(Some(0o755));
+                }
+            }
+            if let Some(mode) = cb.header_arg("tangle-mode") {
+                match parse_mode(mode) {
+                    Some(m) => file.mode = Some(m),
+                    None => {
+                        return Err(TangleError::InvalidMode {
+                            target,
+                            value: mode.to_string(),
+                        })
+                    }
+                }
+            }
+            file.mkdirp |= is_yes(cb.header_arg("mkdirp"));
+            file.content.push_str(&cb.code);
+            file.content.push('\n');
+        }
+    }
+    return Ok(files);
+}
+
+///write the file unless it already has this content; returns whether it changed
+pub fn write_tangled_file(file: &TangledFile) -> Result<bool> {
+    let changed = match fs::read_to_string(&file.path) {
+        Ok(old) => old != file.content,
+        Err(_) => true,
+    };
+    if changed {
+        if file.mkdirp {
+            if let Some(parent) = file.path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+        }
+        fs::write(&file.path, &file.content)?;
+    }
+    if let Some(mode) = file.mode {
+        let mut perms = fs::metadata(&file.path)?.permissions();
+        if perms.mode() & 0o7777 != mode {
+            perms.set_mode(mode);
+            fs::set_permissions(&file.path, perms)?;
+            return Ok(true);
+        }
+    }
+    return Ok(changed);
+}
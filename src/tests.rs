@@ -1,4 +1,8 @@
 use super::code_doc::*;
+use super::mask::*;
+use super::query::*;
+use super::runner::*;
+use super::timestamp::*;
 use super::*;
 
 #[test]
@@ -53,10 +57,16 @@ fn doc_get_code1() {
             CodeBlock {
                 interpreter: "bash".to_string(),
                 code: "h1".to_string(),
+                header_args: Vec::new(),
+                linum: 0,
+                name: None,
             },
             CodeBlock {
                 interpreter: "bash".to_string(),
                 code: "code".to_string(),
+                header_args: Vec::new(),
+                linum: 0,
+                name: None,
             },
         ],
     );
@@ -68,10 +78,16 @@ fn doc_get_code1() {
             CodeBlock {
                 interpreter: "python".to_string(),
                 code: "h2".to_string(),
+                header_args: Vec::new(),
+                linum: 0,
+                name: None,
             },
             CodeBlock {
                 interpreter: "bash".to_string(),
                 code: "code".to_string(),
+                header_args: Vec::new(),
+                linum: 0,
+                name: None,
             },
         ],
     );
@@ -83,10 +99,16 @@ fn doc_get_code1() {
             CodeBlock {
                 interpreter: "bash".to_string(),
                 code: "sec1".to_string(),
+                header_args: Vec::new(),
+                linum: 0,
+                name: None,
             },
             CodeBlock {
                 interpreter: "bash".to_string(),
                 code: "body".to_string(),
+                header_args: Vec::new(),
+                linum: 0,
+                name: None,
             },
         ],
     );
@@ -222,3 +244,2048 @@ echo "two:$2"
         None => assert!(false),
     };
 }
+
+fn deps_doc() -> CodeDoc {
+    let doc_str = r###"
+* build
+:PROPERTIES:
+:DEPENDS: fetch
+:END:
+#+begin_src bash
+echo build
+#+end_src
+* test
+:PROPERTIES:
+:depends: build fetch
+:END:
+* deploy
+:PROPERTIES:
+:DEPENDS: build test
+:END:
+* fetch
+"###;
+    return parse_org_doc(&mut doc_str.as_bytes(), "doc".to_string(), "bash").unwrap();
+}
+
+#[test]
+fn parse_properties() {
+    let doc = deps_doc();
+    let nodes = doc.lookup_nodes(DOC_NODE_ROOT_ID, &["deploy"]);
+    assert_eq!(nodes.len(), 1);
+    assert_eq!(
+        doc.get_node(nodes[0]).property("depends"),
+        Some("build test")
+    );
+    assert_eq!(doc.get_node(nodes[0]).property("missing"), None);
+
+    let codes = doc.get_runnable_code(doc.lookup_nodes(DOC_NODE_ROOT_ID, &["build"])[0], "");
+    assert_eq!(codes[0].code.join("\n"), "echo build");
+}
+
+#[test]
+fn parse_drawer_not_closed() {
+    let doc_str = "* header\n:PROPERTIES:\n:DEPENDS: a\n";
+    match parse_org_doc(&mut doc_str.as_bytes(), "doc".to_string(), "bash") {
+        Err(DocParseError::DrawerNotClosed { linum, .. }) => assert_eq!(linum, 2),
+        _ => panic!(),
+    }
+}
+
+#[test]
+fn dependency_order() {
+    let doc = deps_doc();
+    let deploy = doc.lookup_nodes(DOC_NODE_ROOT_ID, &["deploy"])[0];
+    let order: Vec<String> = doc
+        .get_dependency_order(deploy, ".")
+        .unwrap()
+        .iter()
+        .map(|n| doc.get_node(*n).name().to_string())
+        .collect();
+    assert_eq!(order, vec!["fetch", "build", "test", "deploy"]);
+}
+
+#[test]
+fn dependency_cycle() {
+    let doc_str = r###"
+* a
+:PROPERTIES:
+:DEPENDS: b
+:END:
+* b
+:PROPERTIES:
+:DEPENDS: a
+:END:
+"###;
+    let doc = parse_org_doc(&mut doc_str.as_bytes(), "doc".to_string(), "bash").unwrap();
+    let a = doc.lookup_nodes(DOC_NODE_ROOT_ID, &["a"])[0];
+    let b = doc.lookup_nodes(DOC_NODE_ROOT_ID, &["b"])[0];
+    assert_eq!(
+        doc.get_dependency_order(a, "."),
+        Err(DependencyError::Cycle {
+            nodes: vec![a, b, a]
+        })
+    );
+}
+
+#[test]
+fn dependency_graph_dot() {
+    let doc = deps_doc();
+    let deploy = doc.lookup_nodes(DOC_NODE_ROOT_ID, &["deploy"])[0];
+    assert_eq!(
+        dependency_graph(&doc, &[deploy], ".").unwrap(),
+        r###"digraph jobs {
+    "doc.deploy";
+    "doc.build" -> "doc.deploy";
+    "doc.test" -> "doc.deploy";
+    "doc.fetch" -> "doc.build";
+    "doc.build" -> "doc.test";
+    "doc.fetch" -> "doc.test";
+}
+"###
+    );
+}
+
+#[test]
+fn dependency_without_code() {
+    let doc_str = r###"
+* fetch
+* build
+:PROPERTIES:
+:DEPENDS: fetch
+:END:
+#+begin_src bash
+echo build
+#+end_src
+* check
+:PROPERTIES:
+:DEPENDS: build
+:END:
+* deploy
+:PROPERTIES:
+:DEPENDS: check
+:END:
+#+begin_src bash
+echo deploy
+#+end_src
+"###;
+    let doc = parse_org_doc(&mut doc_str.as_bytes(), "doc".to_string(), "bash").unwrap();
+    let deploy = doc.lookup_nodes(DOC_NODE_ROOT_ID, &["deploy"])[0];
+    let order = doc.get_dependency_order(deploy, ".").unwrap();
+    assert_eq!(order.len(), 4);
+    let tasks = build_tasks(&doc, &order, Some(&[]), &test_settings());
+    let names: Vec<&str> = tasks.iter().map(|t| t.name.as_str()).collect();
+    assert_eq!(names, vec!["build", "deploy"]);
+    assert_eq!(tasks[1].deps, vec![0]);
+}
+
+#[test]
+fn dependency_gets_job_params() {
+    let doc_str = r###"
+* build
+#+begin_src bash :var target
+echo $target
+#+end_src
+* deploy
+:PROPERTIES:
+:DEPENDS: build
+:END:
+#+begin_src bash :var target host=web1
+echo $target $host
+#+end_src
+"###;
+    let doc = parse_org_doc(&mut doc_str.as_bytes(), "doc".to_string(), "bash").unwrap();
+    let deploy = doc.lookup_nodes(DOC_NODE_ROOT_ID, &["deploy"])[0];
+    let order = doc.get_dependency_order(deploy, ".").unwrap();
+    let args = vec!["--target".to_string(), "prod".to_string()];
+    let tasks = build_tasks(&doc, &order, Some(&args), &test_settings());
+    assert_eq!(tasks.len(), 2);
+    assert!(tasks[0]
+        .env
+        .contains(&("target".to_string(), "prod".to_string())));
+    assert!(!tasks[0].env.iter().any(|(k, _)| k == "host"));
+}
+
+fn bash_task(name: &str, code: &str, deps: Vec<usize>) -> Task {
+    return Task {
+        name: name.to_string(),
+        path: name.to_string(),
+        origin: vec![],
+        interpreter: "bash".to_string(),
+        code: code.to_string(),
+        args: vec![],
+        env: vec![],
+        dir: None,
+        secrets: vec![],
+        mask: vec![],
+        deps,
+        check: None,
+        timeout: None,
+        retry: None,
+        confirm: None,
+        session: None,
+        blocks: vec![],
+        stdin: None,
+        inputs: vec![],
+        limits: Limits::default(),
+        unbound: vec![],
+    };
+}
+
+///a sequential run without log, tests set what they need
+fn test_schedule_options() -> ScheduleOptions {
+    return ScheduleOptions {
+        jobs: 1,
+        fail_fast: true,
+        prefix_output: false,
+        log: None,
+        clean_env: false,
+        keep_scripts: None,
+        trace: false,
+        terminal: false,
+    };
+}
+
+///a sequential run of /work/jobs.org, tests set what they need
+fn test_settings() -> RunSettings<'static> {
+    return RunSettings {
+        org_file: Path::new("/work/jobs.org"),
+        lang: None,
+        sep: ".",
+        parallel: false,
+        force: true,
+        timeout: None,
+        dir_base: Path::new("/work"),
+        env: &[],
+        run_id: Some("run1"),
+        pipeline: false,
+        stdin_from: None,
+        dry_run: false,
+    };
+}
+
+#[test]
+fn scheduler_deps() {
+    let tasks = vec![
+        bash_task("a", "exit 0", vec![]),
+        bash_task("b", "exit 5", vec![0]),
+        bash_task("c", "exit 0", vec![1]),
+        bash_task("d", "exit 0", vec![0]),
+    ];
+    let options = ScheduleOptions {
+        jobs: 2,
+        fail_fast: false,
+        prefix_output: true,
+        ..test_schedule_options()
+    };
+    let status = run_tasks(&tasks, &options);
+    assert_eq!(
+        status,
+        vec![
+            TaskStatus::Exited(0),
+            TaskStatus::Exited(5),
+            TaskStatus::Skipped,
+            TaskStatus::Exited(0)
+        ]
+    );
+    assert_eq!(aggregate_status(&status), 5);
+}
+
+#[test]
+fn scheduler_fail_fast() {
+    let tasks = vec![
+        bash_task("slow", "sleep 10", vec![]),
+        bash_task("fail", "exit 2", vec![]),
+        bash_task("later", "exit 0", vec![]),
+    ];
+    let options = ScheduleOptions {
+        jobs: 2,
+        prefix_output: true,
+        ..test_schedule_options()
+    };
+    let status = run_tasks(&tasks, &options);
+    assert_eq!(
+        status,
+        vec![
+            TaskStatus::Killed(15),
+            TaskStatus::Exited(2),
+            TaskStatus::Skipped
+        ]
+    );
+    assert_eq!(aggregate_status(&status), 2);
+}
+
+#[test]
+fn aggregate_interrupted() {
+    let status = vec![
+        TaskStatus::Exited(0),
+        TaskStatus::Interrupted(libc::SIGINT),
+        TaskStatus::Interrupted(libc::SIGINT),
+    ];
+    assert_eq!(aggregate_status(&status), 130);
+    let status = vec![
+        TaskStatus::Killed(libc::SIGTERM),
+        TaskStatus::Exited(3),
+        TaskStatus::Interrupted(libc::SIGTERM),
+    ];
+    assert_eq!(aggregate_status(&status), 3);
+}
+
+#[test]
+fn parallel_child_deps() {
+    let doc_str = r###"
+* fetch
+#+begin_src bash
+echo fetch
+#+end_src
+* ci
+** docs
+:PROPERTIES:
+:DEPENDS: unit
+:END:
+#+begin_src bash
+echo docs
+#+end_src
+** unit
+:PROPERTIES:
+:DEPENDS: fetch
+:END:
+#+begin_src bash
+echo unit
+#+end_src
+** lint
+#+begin_src bash
+echo lint
+#+end_src
+"###;
+    let doc = parse_org_doc(&mut doc_str.as_bytes(), "doc".to_string(), "bash").unwrap();
+    let ci = doc.lookup_nodes(DOC_NODE_ROOT_ID, &["ci"])[0];
+    let settings = RunSettings {
+        parallel: true,
+        ..test_settings()
+    };
+    let tasks = build_tasks(&doc, &[ci], Some(&[]), &settings);
+    let names: Vec<&str> = tasks.iter().map(|t| t.name.as_str()).collect();
+    assert_eq!(names, vec!["fetch", "unit", "docs", "lint"]);
+    let deps: Vec<Vec<usize>> = tasks.iter().map(|t| t.deps.clone()).collect();
+    assert_eq!(deps, vec![vec![], vec![0], vec![1], vec![]]);
+}
+
+#[test]
+fn parse_header_args1() {
+    assert_eq!(
+        parse_header_args(" :tangle out.sh :var x=1 y=2 :mkdirp"),
+        vec![
+            ("tangle".to_string(), "out.sh".to_string()),
+            ("var".to_string(), "x=1 y=2".to_string()),
+            ("mkdirp".to_string(), "".to_string())
+        ]
+    );
+}
+
+#[test]
+fn runnable_code_files() {
+    let doc_str = r###"
+* build
+:PROPERTIES:
+:SOURCES: main.c
+:END:
+#+begin_src bash :creates main.o
+cc -c main.c
+#+end_src
+** link
+:PROPERTIES:
+:CREATES: main
+:END:
+"###;
+    let doc = parse_org_doc(&mut doc_str.as_bytes(), "doc".to_string(), "bash").unwrap();
+    let build = doc.lookup_nodes(DOC_NODE_ROOT_ID, &["build"])[0];
+    let codes = doc.get_runnable_code(build, "");
+    assert_eq!(codes[0].creates, vec!["main", "main.o"]);
+    assert_eq!(codes[0].sources, vec!["main.c"]);
+
+    let link = doc.lookup_nodes(DOC_NODE_ROOT_ID, &["link"])[0];
+    let codes = doc.get_runnable_code(link, "");
+    assert_eq!(codes[0].creates, vec!["main"]);
+    assert!(codes[0].sources.is_empty());
+}
+
+#[test]
+fn build_check_freshness() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("in.txt");
+    let output = dir.path().join("out.txt");
+    let check = BuildCheck {
+        creates: vec![output.clone()],
+        sources: vec![source.clone()],
+        stamp: dir.path().join("stamps").join("job"),
+        hash: "abc".to_string(),
+    };
+    std::fs::write(&source, "in").unwrap();
+    assert!(matches!(check.check(), Freshness::Stale(_)));
+
+    std::fs::write(&output, "out").unwrap();
+    assert!(matches!(check.check(), Freshness::Stale(_)));
+
+    check.record().unwrap();
+    assert_eq!(check.check(), Freshness::UpToDate);
+
+    let changed = BuildCheck {
+        hash: "def".to_string(),
+        ..check
+    };
+    assert_eq!(
+        changed.check(),
+        Freshness::Stale("code changed since last run".to_string())
+    );
+}
+
+#[test]
+fn build_check_paths() {
+    let doc_str = r###"
+* gen
+:PROPERTIES:
+:CREATES: out/site.html
+:SOURCES: src/index.md
+:END:
+#+begin_src bash
+make site
+#+end_src
+"###;
+    let doc = parse_org_doc(&mut doc_str.as_bytes(), "doc".to_string(), "bash").unwrap();
+    let gen = doc.lookup_nodes(DOC_NODE_ROOT_ID, &["gen"])[0];
+    let settings = RunSettings {
+        force: false,
+        ..test_settings()
+    };
+    let tasks = build_tasks(&doc, &[gen], Some(&[]), &settings);
+    let check = tasks[0].check.as_ref().unwrap();
+    assert_eq!(check.creates, vec![PathBuf::from("/work/out/site.html")]);
+    assert_eq!(check.sources, vec![PathBuf::from("/work/src/index.md")]);
+    assert_eq!(check.stamp, PathBuf::from("/work/.orgjob/stamps/doc.gen"));
+}
+
+#[test]
+fn tangle_files() {
+    let doc_str = r###"
+* script
+#+begin_src bash :tangle bin/run.sh :mkdirp yes :shebang #!/bin/bash
+echo one
+#+end_src
+#+begin_src python :tangle conf.py
+x = 1
+#+end_src
+** more
+#+begin_src bash :tangle bin/run.sh
+echo two
+#+end_src
+#+begin_src bash
+echo not tangled
+#+end_src
+#+begin_src bash :tangle conf.sh :tangle-mode (identifier #o600)
+A=1
+#+end_src
+"###;
+    let doc = parse_org_doc(&mut doc_str.as_bytes(), "doc".to_string(), "bash").unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let org_file = dir.path().join("jobs.org");
+    let all: Vec<DocNodeId> = ((DOC_NODE_ROOT_ID + 1)..=doc.len()).collect();
+    let files = collect_tangled_files(&doc, &all, &org_file).unwrap();
+    assert_eq!(files.len(), 3);
+    assert_eq!(files[0].path, dir.path().join("bin/run.sh"));
+    assert_eq!(files[0].content, "#!/bin/bash\necho one\necho two\n");
+    assert_eq!(files[0].mode, Some(0o755));
+    assert!(files[0].mkdirp);
+    assert_eq!(files[1].content, "x = 1\n");
+    assert_eq!(files[1].mode, None);
+    assert_eq!(files[2].mode, Some(0o600));
+
+    assert!(write_tangled_file(&files[0]).unwrap());
+    assert!(!write_tangled_file(&files[0]).unwrap());
+    assert_eq!(
+        std::fs::read_to_string(dir.path().join("bin/run.sh")).unwrap(),
+        files[0].content
+    );
+}
+
+#[test]
+fn export_script_runs() {
+    let tasks = vec![
+        bash_task("dep", "echo dep", vec![]),
+        bash_task("job", "test \"$1\" = one && exit 7", vec![0]),
+    ];
+    let here = ExportPlace {
+        script_dir: Path::new("/work"),
+        base: Path::new("/work"),
+    };
+    let script = export_script("jobs.org", "doc.job", &tasks, 1, &[], &here);
+    assert!(script.starts_with("#!/usr/bin/env bash\n# exported by orgjob from jobs.org\n"));
+    let status = run_code("bash", &script, &["one"]).unwrap();
+    assert_eq!(status.code(), Some(7));
+
+    let tasks = vec![
+        bash_task("dep", "exit 3", vec![]),
+        bash_task("job", "exit 0", vec![0]),
+    ];
+    let script = export_script("jobs.org", "doc.job", &tasks, 1, &[], &here);
+    let status = run_code("bash", &script, &["one"]).unwrap();
+    assert_eq!(status.code(), Some(3));
+}
+
+#[test]
+fn export_script_inputs() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut upper = bash_task("upper", "tr a-z A-Z", vec![0]);
+    upper.stdin = Some(TaskInput::Output(0));
+    let mut job = bash_task(
+        "job",
+        "read l\n[ \"$l\" = abc ] && [ \"$X\" = hello ] || exit 9\n[ \"$(nice)\" -ge 1 ] || exit 8\nhead -c 4096 /dev/zero > big",
+        vec![0],
+    );
+    job.inputs = vec![("X".to_string(), 0)];
+    job.stdin = Some(TaskInput::Example {
+        name: "in".to_string(),
+        text: "abc\n".to_string(),
+    });
+    job.dir = Some(dir.path().to_path_buf());
+    job.limits = Limits {
+        file_size: Some(1024),
+        nice: Some(1),
+        ..Limits::default()
+    };
+    let tasks = vec![bash_task("hello", "echo hello", vec![]), upper, job];
+    let here = ExportPlace {
+        script_dir: dir.path(),
+        base: Path::new("/work"),
+    };
+    let script = export_script("jobs.org", "doc.job", &tasks, 2, &[], &here);
+    let path = dir.path().join("job.sh");
+    std::fs::write(&path, &script).unwrap();
+    let out = std::process::Command::new("bash")
+        .arg(&path)
+        .stdin(std::process::Stdio::null())
+        .output()
+        .unwrap();
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "hello\nHELLO\n");
+    //the file size limit stops the job with SIGXFSZ
+    assert_eq!(out.status.code(), Some(128 + libc::SIGXFSZ));
+    assert_eq!(
+        std::fs::metadata(dir.path().join("big")).unwrap().len(),
+        1024
+    );
+}
+
+#[test]
+fn export_script_moves() {
+    let dir = tempfile::tempdir().unwrap();
+    let org = dir.path().join("old/org");
+    std::fs::create_dir_all(org.join("work")).unwrap();
+    std::fs::create_dir_all(dir.path().join("old/bin")).unwrap();
+    let mut job = bash_task(
+        "job",
+        "[ \"$(pwd)\" = \"$(cd \"$ORGJOB_DIR/work\" && pwd)\" ] || exit 9\nn=$(cat count 2>/dev/null || echo 0)\necho $((n + 1)) > count\n[ $n -ge 2 ]",
+        vec![],
+    );
+    job.env = vec![("ORGJOB_DIR".to_string(), org.to_string_lossy().to_string())];
+    job.dir = Some(org.join("work"));
+    job.retry = Some(RetryPolicy {
+        retries: 2,
+        delay: Duration::from_millis(10),
+        backoff: 2.0,
+        exit_codes: vec![1],
+    });
+    let script_dir = dir.path().join("old/bin");
+    let here = ExportPlace {
+        script_dir: &script_dir,
+        base: &org,
+    };
+    let script = export_script("jobs.org", "doc.job", &[job], 0, &[], &here);
+    assert!(!script.contains(&*org.to_string_lossy()));
+    std::fs::write(dir.path().join("old/bin/job.sh"), &script).unwrap();
+    //the script and the org file's directory move together
+    std::fs::rename(dir.path().join("old"), dir.path().join("new")).unwrap();
+    let status = std::process::Command::new("bash")
+        .arg(dir.path().join("new/bin/job.sh"))
+        .stdin(std::process::Stdio::null())
+        .status()
+        .unwrap();
+    assert_eq!(status.code(), Some(0));
+    let count = std::fs::read_to_string(dir.path().join("new/org/work/count")).unwrap();
+    assert_eq!(count, "3\n");
+
+    let mut slow = bash_task("slow", "sleep 5", vec![]);
+    slow.timeout = Some(Duration::from_millis(200));
+    let script = export_script("jobs.org", "doc.slow", &[slow], 0, &[], &here);
+    let started = Instant::now();
+    let status = run_code("bash", &script, &Vec::<&OsStr>::new()).unwrap();
+    assert_eq!(status.code(), Some(124));
+    assert!(started.elapsed() < Duration::from_secs(3));
+}
+
+#[test]
+fn parse_params1() {
+    assert_eq!(
+        parse_params(r#"host port=8080 msg="hello world""#),
+        vec![
+            Param {
+                name: "host".to_string(),
+                default: None,
+                choices: vec![],
+                secret: false,
+            },
+            Param {
+                name: "port".to_string(),
+                default: Some("8080".to_string()),
+                choices: vec![],
+                secret: false,
+            },
+            Param {
+                name: "msg".to_string(),
+                default: Some("hello world".to_string()),
+                choices: vec![],
+                secret: false,
+            },
+        ]
+    );
+}
+
+#[test]
+fn bind_params1() {
+    let params = parse_params("host port=8080");
+    let bound = bind_params(&params, &["--host", "a", "x", "--port=1", "--", "--y"]).unwrap();
+    assert_eq!(
+        bound.values,
+        vec![
+            ("host".to_string(), "a".to_string()),
+            ("port".to_string(), "1".to_string())
+        ]
+    );
+    assert_eq!(bound.positional, vec!["x", "--y"]);
+
+    let bound = bind_params(&params, &["--host=b"]).unwrap();
+    assert_eq!(bound.values[1], ("port".to_string(), "8080".to_string()));
+
+    assert_eq!(
+        bind_params(&params, &["--port", "1"]),
+        Err(ParamError::Missing("host".to_string()))
+    );
+    assert_eq!(
+        bind_params(&params, &["--hots", "1"]),
+        Err(ParamError::Unknown("hots".to_string()))
+    );
+    assert_eq!(
+        bind_params(&params, &["--host"]),
+        Err(ParamError::NoValue("host".to_string()))
+    );
+    assert!(bind_params(&params, &["--help"]).unwrap().help);
+    assert_eq!(
+        bind_params(&parse_params("bad-name"), &[] as &[&str]),
+        Err(ParamError::InvalidName("bad-name".to_string()))
+    );
+    assert_eq!(
+        bind_params(&parse_params("PATH=/bin"), &[] as &[&str]),
+        Err(ParamError::Reserved("PATH".to_string()))
+    );
+    assert_eq!(
+        bind_params(&parse_params("ORGJOB_JOB"), &[] as &[&str]),
+        Err(ParamError::Reserved("ORGJOB_JOB".to_string()))
+    );
+
+    //without declarations all arguments are positional
+    let bound = bind_params(&[], &["--host", "a"]).unwrap();
+    assert_eq!(bound.positional, vec!["--host", "a"]);
+}
+
+#[test]
+fn runnable_code_params() {
+    let doc_str = r###"
+* deploy
+:PROPERTIES:
+:ARGS: env=staging
+:END:
+#+begin_src python :var count=3
+print(count)
+#+end_src
+** web
+#+begin_src python :var env=prod host
+print(host)
+#+end_src
+"###;
+    let doc = parse_org_doc(&mut doc_str.as_bytes(), "doc".to_string(), "bash").unwrap();
+    let web = doc.lookup_nodes(DOC_NODE_ROOT_ID, &["web"])[0];
+    let codes = doc.get_runnable_code(web, ".");
+    let names: Vec<&str> = codes[0].params.iter().map(|p| p.name.as_str()).collect();
+    assert_eq!(names, vec!["env", "count", "host"]);
+    assert_eq!(codes[0].params[0].default, Some("staging".to_string()));
+}
+
+#[test]
+fn python_params() {
+    let code = param_prelude("python3", &["count"]) + "import sys\nsys.exit(int(count))\n";
+    let options = RunOptions {
+        env: vec![("count".to_string(), "6".to_string())],
+        ..RunOptions::default()
+    };
+    let no_args: Vec<&str> = vec![];
+    let status = spawn_code("python3", &code, &no_args, &options)
+        .unwrap()
+        .wait()
+        .unwrap();
+    assert_eq!(status.code(), Some(6));
+}
+
+#[test]
+fn python_quote() {
+    assert_eq!(py_quote("it's"), r"'it\'s'");
+    let text = "a\\b 'c'\n\t\u{1b}[0m \u{e9}\u{1f600}";
+    let codes: Vec<String> = text.chars().map(|c| (c as u32).to_string()).collect();
+    let code = format!(
+        "import sys\nsys.exit(0 if {} == ''.join(map(chr, [{}])) else 1)\n",
+        py_quote(text),
+        codes.join(", ")
+    );
+    let no_args: Vec<&str> = vec![];
+    let status = spawn_code("python3", &code, &no_args, &RunOptions::default())
+        .unwrap()
+        .wait()
+        .unwrap();
+    assert_eq!(status.code(), Some(0));
+}
+
+#[test]
+fn log_line_literals() {
+    let msg = "mail @example $x #{x} \\ it's \u{e9}";
+    assert_eq!(single_quote("it's \\"), r"'it\'s \\'");
+    assert_eq!(
+        log_line("ruby", msg).unwrap(),
+        "$stderr.puts('mail @example $x #{x} \\\\ it\\'s \u{e9}')\n"
+    );
+    for (interpreter, flag) in [("perl", "-e"), ("node", "-e")].iter() {
+        let out = std::process::Command::new(interpreter)
+            .arg(flag)
+            .arg(log_line(interpreter, msg).unwrap())
+            .output()
+            .unwrap();
+        assert_eq!(String::from_utf8_lossy(&out.stderr), format!("{}\n", msg));
+    }
+}
+
+#[test]
+fn stepwise_probe_without_code() {
+    let doc_str = r###"
+* tools
+** notes
+** lint
+#+begin_src bash
+lint
+#+end_src
+"###;
+    let doc = parse_org_doc(&mut doc_str.as_bytes(), "doc".to_string(), "bash").unwrap();
+    let tools = doc.lookup_nodes(DOC_NODE_ROOT_ID, &["tools"])[0];
+    let tasks = build_tasks(&doc, &[tools], Some(&[]), &test_settings());
+    assert_eq!(tasks.len(), 1);
+    assert!(tasks[0].code.contains("\nlint\n"));
+}
+
+#[test]
+fn bind_params_choices() {
+    let doc_str = r###"
+* deploy
+:PROPERTIES:
+:ARGS: target token region=us
+:CHOICES: target=staging|prod
+:SECRET: token
+:END:
+#+begin_src bash
+echo $target
+#+end_src
+"###;
+    let doc = parse_org_doc(&mut doc_str.as_bytes(), "doc".to_string(), "bash").unwrap();
+    let deploy = doc.lookup_nodes(DOC_NODE_ROOT_ID, &["deploy"])[0];
+    let params = &doc.get_runnable_code(deploy, ".")[0].params;
+    assert_eq!(params[0].choices, vec!["staging", "prod"]);
+    assert!(!params[0].secret);
+    assert!(params[1].secret);
+
+    assert_eq!(
+        bind_params(params, &["--target", "dev", "--token", "x"]),
+        Err(ParamError::InvalidChoice {
+            name: "target".to_string(),
+            value: "dev".to_string()
+        })
+    );
+
+    let mut asked = Vec::new();
+    let bound = bind_params_with(params, &["--target", "prod"], |p| {
+        asked.push(p.name.clone());
+        Ok("t0ken".to_string())
+    })
+    .unwrap();
+    assert_eq!(asked, vec!["token"]);
+    assert_eq!(bound.values[1], ("token".to_string(), "t0ken".to_string()));
+}
+
+#[test]
+fn scheduler_timeout() {
+    let mut slow = bash_task("slow", "sleep 10 &\nwait", vec![]);
+    slow.timeout = Some(std::time::Duration::from_millis(200));
+    let tasks = vec![slow, bash_task("after", "exit 0", vec![0])];
+    let options = ScheduleOptions {
+        prefix_output: true,
+        ..test_schedule_options()
+    };
+    let start = std::time::Instant::now();
+    let status = run_tasks(&tasks, &options);
+    assert!(start.elapsed() < std::time::Duration::from_secs(5));
+    assert_eq!(
+        status,
+        vec![
+            TaskStatus::TimedOut(std::time::Duration::from_millis(200)),
+            TaskStatus::Skipped
+        ]
+    );
+    assert_eq!(aggregate_status(&status), 124);
+}
+
+#[test]
+fn scheduler_background_processes() {
+    let dir = tempfile::tempdir().unwrap();
+    let pid_file = dir.path().join("pid");
+    //a killed process nobody reaps stays as a zombie
+    let alive = |pid: &str| match std::fs::read_to_string(format!("/proc/{}/stat", pid.trim())) {
+        Ok(stat) => !stat.contains(") Z "),
+        Err(_) => false,
+    };
+    //what a job starts in the background outlives it
+    let code = format!(
+        "sleep 30 >/dev/null 2>&1 &\necho $! > {}",
+        pid_file.display()
+    );
+    let start = std::time::Instant::now();
+    let status = run_tasks(&[bash_task("bg", &code, vec![])], &test_schedule_options());
+    assert!(start.elapsed() < std::time::Duration::from_secs(2));
+    assert_eq!(status, vec![TaskStatus::Exited(0)]);
+    let pid = std::fs::read_to_string(&pid_file).unwrap();
+    assert!(alive(&pid));
+    unsafe { libc::kill(pid.trim().parse().unwrap(), libc::SIGKILL) };
+    //unless it is stopped, then what ignores SIGTERM gets SIGKILL
+    let code = format!(
+        "(trap '' TERM; sleep 30) &\necho $! > {}\nwait",
+        pid_file.display()
+    );
+    let mut slow = bash_task("slow", &code, vec![]);
+    slow.timeout = Some(std::time::Duration::from_millis(200));
+    let status = run_tasks(&[slow], &test_schedule_options());
+    assert_eq!(
+        status,
+        vec![TaskStatus::TimedOut(std::time::Duration::from_millis(200))]
+    );
+    let pid = std::fs::read_to_string(&pid_file).unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(100));
+    assert!(!alive(&pid));
+}
+
+#[test]
+fn parse_duration1() {
+    use std::time::Duration;
+    assert_eq!(parse_duration("90"), Some(Duration::from_secs(90)));
+    assert_eq!(parse_duration("1.5s"), Some(Duration::from_millis(1500)));
+    assert_eq!(parse_duration("250ms"), Some(Duration::from_millis(250)));
+    assert_eq!(parse_duration("5m"), Some(Duration::from_secs(300)));
+    assert_eq!(parse_duration("2h"), Some(Duration::from_secs(7200)));
+    assert_eq!(parse_duration("soon"), None);
+    assert_eq!(parse_duration("3d"), None);
+}
+
+#[test]
+fn scheduler_retry() {
+    use std::time::Duration;
+    let dir = tempfile::tempdir().unwrap();
+    let count = dir.path().join("count");
+    let flaky_code = format!(
+        "echo x >> {0}\n[ $(wc -l < {0}) -ge 3 ] || exit 75",
+        count.display()
+    );
+    let policy = RetryPolicy {
+        retries: 3,
+        delay: Duration::from_millis(10),
+        backoff: 2.0,
+        exit_codes: vec![75],
+    };
+    let mut flaky = bash_task("flaky", &flaky_code, vec![]);
+    flaky.retry = Some(policy.clone());
+    let mut broken = bash_task("broken", "exit 1", vec![]);
+    broken.retry = Some(policy.clone());
+    let tasks = vec![flaky, broken, bash_task("after", "exit 0", vec![0])];
+    let options = ScheduleOptions {
+        jobs: 2,
+        fail_fast: false,
+        prefix_output: true,
+        ..test_schedule_options()
+    };
+    let status = run_tasks(&tasks, &options);
+    assert_eq!(
+        status,
+        vec![
+            TaskStatus::Exited(0),
+            TaskStatus::Exited(1),
+            TaskStatus::Exited(0)
+        ]
+    );
+    assert_eq!(std::fs::read_to_string(&count).unwrap().lines().count(), 3);
+    assert_eq!(policy.delay_after(1), Duration::from_millis(10));
+    assert_eq!(policy.delay_after(3), Duration::from_millis(40));
+}
+
+#[test]
+fn retry_steps() {
+    let doc_str = r###"
+* deploy
+:PROPERTIES:
+:RETRIES: 2
+:RETRY_ON: 75
+:END:
+** build
+#+begin_src bash
+make
+#+end_src
+** upload
+#+begin_src bash
+upload
+#+end_src
+"###;
+    let doc = parse_org_doc(&mut doc_str.as_bytes(), "doc".to_string(), "bash").unwrap();
+    let deploy = doc.lookup_nodes(DOC_NODE_ROOT_ID, &["deploy"])[0];
+    let settings = RunSettings {
+        org_file: Path::new("jobs.org"),
+        ..test_settings()
+    };
+    let tasks = build_tasks(&doc, &[deploy], Some(&[]), &settings);
+    let names: Vec<&str> = tasks.iter().map(|t| t.name.as_str()).collect();
+    assert_eq!(names, vec!["build", "upload"]);
+    assert_eq!(tasks[1].deps, vec![0]);
+    let policy = tasks[1].retry.as_ref().unwrap();
+    assert_eq!((policy.retries, policy.exit_codes.clone()), (2, vec![75]));
+}
+
+#[test]
+fn run_log_history() {
+    let dir = tempfile::tempdir().unwrap();
+    let job_dir = job_log_dir(dir.path(), "doc.deploy");
+    let log = RunLog::create(&job_dir, &new_run_id(), "doc.deploy", Path::new("jobs.org")).unwrap();
+    let mut deploy = bash_task("deploy", "echo out\necho err >&2\nexit 3", vec![]);
+    deploy.env = vec![
+        ("host".to_string(), "web1".to_string()),
+        ("token".to_string(), "hunter2".to_string()),
+    ];
+    deploy.secrets = vec!["token".to_string()];
+    let tasks = vec![deploy, bash_task("after", "exit 0", vec![0])];
+    let options = ScheduleOptions {
+        log: Some(log),
+        ..test_schedule_options()
+    };
+    let status = run_tasks(&tasks, &options);
+    let log = options.log.unwrap();
+    log.finish(&tasks, &status, aggregate_status(&status))
+        .unwrap();
+
+    let runs = list_runs(dir.path(), None);
+    assert_eq!(runs.len(), 1);
+    assert_eq!(runs[0].dir, log.dir);
+    assert_eq!(runs[0].job, "doc.deploy");
+    assert_eq!(runs[0].status, Some(3));
+    let task_dir = log.dir.join("01-deploy");
+    let env = std::fs::read_to_string(task_dir.join("env")).unwrap();
+    assert_eq!(env, "host=web1\ntoken=****\n");
+    assert!(!task_dir.with_file_name("02-after").exists());
+    let text = read_run_log(&runs[0]).unwrap();
+    assert!(text.contains("task 02-after: skipped\n"));
+    assert!(text.contains("attempt 1: failed with exit code 3"));
+    assert!(text.contains("--- stdout\nout\n--- stderr\nerr\n"));
+}
+
+#[test]
+fn run_log_terminal() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = RunLog::create(dir.path(), "run1", "doc.edit", Path::new("jobs.org")).unwrap();
+    let options = ScheduleOptions {
+        log: Some(log),
+        terminal: true,
+        ..test_schedule_options()
+    };
+    let status = run_tasks(&[bash_task("edit", "exit 0", vec![])], &options);
+    assert_eq!(status, vec![TaskStatus::Exited(0)]);
+    let task_dir = options.log.unwrap().dir.join("01-edit");
+    assert!(task_dir.join("script").exists());
+    assert!(!task_dir.join("stdout").exists());
+    let text = std::fs::read_to_string(task_dir.join("status")).unwrap();
+    assert!(text.starts_with("attempt 1: output went to the terminal\nattempt 1: ok"));
+}
+
+#[test]
+fn format_time1() {
+    use std::time::{Duration, UNIX_EPOCH};
+    let t = UNIX_EPOCH + Duration::from_millis(1_709_210_096_789);
+    assert_eq!(format_time(t), "2024-02-29T12:34:56.789Z");
+    assert_eq!(format_time(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+}
+
+#[test]
+fn run_reports() {
+    use std::time::Duration;
+    let case = |name: &str, status: TaskStatus, stdout: &str| ReportCase {
+        name: name.to_string(),
+        interpreter: "bash".to_string(),
+        run: TaskRun {
+            status,
+            duration: Duration::from_millis(1500),
+            attempts: 1,
+        },
+        stdout: stdout.to_string(),
+        stderr: String::new(),
+    };
+    let cases = vec![
+        case("doc.ci.build", TaskStatus::Exited(0), "say \"hi\"\n"),
+        case("doc.ci.test", TaskStatus::Exited(3), "a < b & c\n"),
+        case("doc.ci.deploy", TaskStatus::Skipped, ""),
+    ];
+    let json = json_report("doc.ci", 3, Duration::from_secs(2), &cases);
+    assert!(json.contains("\"name\": \"doc.ci.build\""));
+    assert!(json.contains("\"stdout\": \"say \\\"hi\\\"\\n\""));
+    assert!(json.contains("\"status\": \"failed\",\n      \"message\": \"failed with exit code 3\",\n      \"exit_code\": 3,\n      \"duration\": 1.500"));
+    assert!(json.contains(
+        "\"status\": \"skipped\",\n      \"message\": \"skipped\",\n      \"exit_code\": null"
+    ));
+    let xml = junit_report("doc.ci", Duration::from_secs(2), &cases);
+    assert!(xml.contains(
+        "<testsuite name=\"doc.ci\" tests=\"3\" failures=\"1\" errors=\"0\" skipped=\"1\" time=\"2.000\">"
+    ));
+    assert!(xml.contains("<failure message=\"failed with exit code 3\" type=\"failed\"/>"));
+    assert!(xml.contains("<system-out>a &lt; b &amp; c\n</system-out>"));
+
+    assert_eq!(truncate_output("short", 10), "short");
+    assert_eq!(
+        truncate_output("0123456789", 4),
+        "[6 bytes truncated]\n6789"
+    );
+    assert_eq!(truncate_output("aé", 1), "[3 bytes truncated]\n");
+}
+
+#[test]
+fn dry_run_plan1() {
+    let doc_str = r###"
+#+begin_src bash
+set -e
+#+end_src
+* build
+#+begin_src bash
+make
+#+end_src
+* deploy
+:PROPERTIES:
+:DEPENDS: build
+:ARGS: host token
+:SECRET: token
+:DIR: site
+:END:
+#+begin_src bash
+upload
+#+end_src
+** web
+#+begin_src bash
+restart web
+#+end_src
+"###;
+    let doc = parse_org_doc(&mut doc_str.as_bytes(), "doc".to_string(), "bash").unwrap();
+    let deploy = doc.lookup_nodes(DOC_NODE_ROOT_ID, &["deploy"])[0];
+    let order = doc.get_dependency_order(deploy, ".").unwrap();
+    let settings = test_settings();
+    let args = ["--host=web1".to_string(), "--token=hunter2".to_string()];
+    let tasks = build_tasks(&doc, &order, Some(&args), &settings);
+    let plan = dry_run_plan(
+        "doc.deploy",
+        Path::new("jobs.org"),
+        Path::new("/work"),
+        &tasks,
+    );
+    let expected = r###"dry run of doc.deploy, nothing is started
+
+1. doc.build
+   working directory: /work
+   command: bash <script>
+   arguments: none
+   environment:
+     ORGJOB_FILE='/work/jobs.org'
+     ORGJOB_DIR='/work'
+     ORGJOB_JOB='doc.build'
+     ORGJOB_RUN_ID='run1'
+   depends on: nothing
+   code:
+     jobs.org:2 doc (ancestor)
+     jobs.org:6 doc.build
+
+2. doc.deploy
+   working directory: /work/site
+   command: bash <script>
+   arguments: none
+   environment:
+     host='web1'
+     token=****
+     ORGJOB_FILE='/work/jobs.org'
+     ORGJOB_DIR='/work'
+     ORGJOB_JOB='doc.deploy'
+     ORGJOB_RUN_ID='run1'
+   depends on: doc.build
+   code:
+     jobs.org:2 doc (ancestor)
+     jobs.org:16 doc.deploy
+     jobs.org:20 doc.deploy.web (descendant)
+"###;
+    assert_eq!(plan, expected);
+    //a dry run does not ask for what is missing
+    let settings = RunSettings {
+        dry_run: true,
+        ..test_settings()
+    };
+    let tasks = build_tasks(&doc, &order, Some(&args[..1]), &settings);
+    assert_eq!(tasks[1].unbound, vec!["token"]);
+    assert!(!tasks[1].env.iter().any(|(k, _)| k == "token"));
+    let plan = dry_run_plan(
+        "doc.deploy",
+        Path::new("jobs.org"),
+        Path::new("/work"),
+        &tasks,
+    );
+    assert!(plan.contains("   unbound parameters: token\n   environment:\n     host='web1'\n"));
+}
+
+#[test]
+fn confirm_gates() {
+    let doc_str = r###"
+* prod   :danger:ops:
+** deploy
+#+begin_src bash
+deploy
+#+end_src
+** status
+:PROPERTIES:
+:CONFIRM: no
+:END:
+* teardown
+:PROPERTIES:
+:CONFIRM: destroy-all
+:END:
+* build :ci:
+"###;
+    let doc = parse_org_doc(&mut doc_str.as_bytes(), "doc".to_string(), "bash").unwrap();
+    let find = |q: &[&str]| doc.lookup_nodes(DOC_NODE_ROOT_ID, q)[0];
+    let prod = find(&["prod"]);
+    assert_eq!(doc.get_node(prod).name(), "prod");
+    assert!(doc.get_node(prod).has_tag("danger"));
+    assert!(doc.get_node(prod).has_tag("ops"));
+    assert_eq!(
+        job_confirm(&doc, find(&["prod", "deploy"])),
+        Some("prod".to_string())
+    );
+    assert_eq!(job_confirm(&doc, find(&["status"])), None);
+    assert_eq!(
+        job_confirm(&doc, find(&["teardown"])),
+        Some("destroy-all".to_string())
+    );
+    assert_eq!(job_confirm(&doc, find(&["build"])), None);
+}
+
+#[test]
+fn job_dirs() {
+    let doc_str = r###"
+* site
+:PROPERTIES:
+:DIR: www
+:CREATES: index.html
+:END:
+#+begin_src bash
+build
+#+end_src
+** assets
+#+begin_src bash :dir /srv/assets
+copy
+#+end_src
+* loose
+#+begin_src bash
+ls
+#+end_src
+"###;
+    let doc = parse_org_doc(&mut doc_str.as_bytes(), "doc".to_string(), "bash").unwrap();
+    let find = |q: &[&str]| doc.lookup_nodes(DOC_NODE_ROOT_ID, q)[0];
+    let settings = RunSettings {
+        force: false,
+        ..test_settings()
+    };
+    let dir = |n: DocNodeId| job_dir(&doc, n, "bash", &settings);
+    assert_eq!(dir(find(&["site"])), PathBuf::from("/work/www"));
+    assert_eq!(dir(find(&["assets"])), PathBuf::from("/srv/assets"));
+    assert_eq!(dir(find(&["loose"])), PathBuf::from("/work"));
+    let tasks = build_tasks(&doc, &[find(&["site"])], Some(&[]), &settings);
+    let check = tasks[0].check.as_ref().unwrap();
+    assert_eq!(check.creates, vec![PathBuf::from("/work/www/index.html")]);
+}
+
+#[test]
+fn parse_dotenv1() {
+    let text =
+        "# comment\n\nA=1\nexport B = 'x # y'\nC=\"two\\nlines \\\"q\\\"\"\nD=plain # note\nE=\n";
+    let vars = parse_dotenv(text).unwrap();
+    let expected = [
+        ("A", "1"),
+        ("B", "x # y"),
+        ("C", "two\nlines \"q\""),
+        ("D", "plain"),
+        ("E", ""),
+    ];
+    let expected: Vec<(String, String)> = expected
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    assert_eq!(vars, expected);
+    assert_eq!(
+        parse_dotenv("ok=1\nnot a var\n"),
+        Err(DotenvError::InvalidLine {
+            linum: 2,
+            line: "not a var".to_string()
+        })
+    );
+    assert_eq!(
+        parse_dotenv("A='open\n"),
+        Err(DotenvError::QuoteNotClosed {
+            linum: 1,
+            line: "A='open".to_string()
+        })
+    );
+}
+
+#[test]
+fn job_env() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("base.env"), "A=base\nB=base\nC=base\n").unwrap();
+    std::fs::write(dir.path().join("job.env"), "B=job\n").unwrap();
+    let org_file = dir.path().join("jobs.org");
+    let doc_str = r###"
+#+ENV_FILE: base.env
+* deploy
+:PROPERTIES:
+:ENV_FILE: job.env
+:ARGS: C=param
+:END:
+#+begin_src bash
+deploy
+#+end_src
+"###;
+    let doc = parse_org_doc(&mut doc_str.as_bytes(), "doc".to_string(), "bash").unwrap();
+    let deploy = doc.lookup_nodes(DOC_NODE_ROOT_ID, &["deploy"])[0];
+    let overrides = [("A".to_string(), "cli".to_string())];
+    let settings = RunSettings {
+        org_file: &org_file,
+        dir_base: dir.path(),
+        env: &overrides,
+        ..test_settings()
+    };
+    let tasks = build_tasks(&doc, &[deploy], Some(&[]), &settings);
+    let env: Vec<(&str, &str)> = tasks[0]
+        .env
+        .iter()
+        .filter(|(k, _)| !k.starts_with("ORGJOB_FILE") && !k.starts_with("ORGJOB_DIR"))
+        .map(|(k, v)| (k.as_str(), v.as_str()))
+        .collect();
+    assert_eq!(
+        env,
+        vec![
+            ("B", "job"),
+            ("A", "cli"),
+            ("C", "param"),
+            ("ORGJOB_JOB", "doc.deploy"),
+            ("ORGJOB_RUN_ID", "run1")
+        ]
+    );
+    assert_eq!(tasks[0].secrets, vec!["A", "C", "B"]);
+}
+
+#[test]
+fn session_steps() {
+    let doc_str = r###"
+* deploy
+#+begin_src bash :session
+count=$((count+1))
+echo "prelude $count"
+#+end_src
+** build
+#+begin_src bash
+built=yes
+printf 'no newline'
+#+end_src
+** check
+#+begin_src bash
+echo "built=$built count=$count args=$*"
+echo warning >&2
+#+end_src
+** fail
+#+begin_src bash
+false
+#+end_src
+** never
+#+begin_src bash
+echo never
+#+end_src
+"###;
+    let doc = parse_org_doc(&mut doc_str.as_bytes(), "doc".to_string(), "bash").unwrap();
+    let deploy = doc.lookup_nodes(DOC_NODE_ROOT_ID, &["deploy"])[0];
+    let settings = RunSettings {
+        org_file: Path::new("jobs.org"),
+        dir_base: Path::new("."),
+        ..test_settings()
+    };
+    let args = vec!["a b".to_string()];
+    let tasks = build_tasks(&doc, &[deploy], Some(&args), &settings);
+    let names: Vec<&str> = tasks.iter().map(|t| t.name.as_str()).collect();
+    assert_eq!(names, vec!["build", "check", "fail", "never"]);
+    assert_eq!(tasks[1].session.as_deref(), Some("default"));
+    let ids: Vec<&str> = tasks[1].blocks.iter().map(|(id, _)| id.as_str()).collect();
+    assert_eq!(ids, vec!["", "3", "13"]);
+
+    let dir = tempfile::tempdir().unwrap();
+    let log = RunLog::create(dir.path(), "run1", "doc.deploy", Path::new("jobs.org")).unwrap();
+    let options = ScheduleOptions {
+        log: Some(log),
+        ..test_schedule_options()
+    };
+    let status = run_tasks(&tasks, &options);
+    assert_eq!(
+        status,
+        vec![
+            TaskStatus::Exited(0),
+            TaskStatus::Exited(0),
+            TaskStatus::Exited(1),
+            TaskStatus::Skipped
+        ]
+    );
+    let log = options.log.unwrap();
+    assert_eq!(
+        log.read_output(0, &tasks[0], 1),
+        ("prelude 1\nno newline".to_string(), String::new())
+    );
+    assert_eq!(
+        log.read_output(1, &tasks[1], 1),
+        (
+            "built=yes count=1 args=a b\n".to_string(),
+            "warning\n".to_string()
+        )
+    );
+}
+
+#[test]
+fn python_session() {
+    let mut tasks = Vec::new();
+    for (name, code) in [
+        ("set", "x = 41"),
+        ("exit", "x += 1\nimport sys\nsys.exit(4)"),
+        ("get", "print(x, sys.argv[1:])"),
+    ]
+    .iter()
+    {
+        let mut t = bash_task(name, "", vec![]);
+        t.interpreter = "python".to_string();
+        t.session = Some("py".to_string());
+        t.blocks = vec![(name.to_string(), code.to_string())];
+        t.args = vec!["arg".to_string()];
+        tasks.push(t);
+    }
+    let dir = tempfile::tempdir().unwrap();
+    let log = RunLog::create(dir.path(), "run1", "py", Path::new("jobs.org")).unwrap();
+    let options = ScheduleOptions {
+        jobs: 2,
+        fail_fast: false,
+        prefix_output: true,
+        log: Some(log),
+        ..test_schedule_options()
+    };
+    let status = run_tasks(&tasks, &options);
+    assert_eq!(
+        status,
+        vec![
+            TaskStatus::Exited(0),
+            TaskStatus::Exited(4),
+            TaskStatus::Exited(0)
+        ]
+    );
+    let log = options.log.unwrap();
+    assert_eq!(log.read_output(2, &tasks[2], 1).0, "42 ['arg']\n");
+}
+
+#[test]
+fn pipeline_tasks() {
+    let doc_str = r###"
+* report
+:PROPERTIES:
+:ARGS: limit=2
+:END:
+#+NAME: fetch
+#+begin_src bash
+printf 'b\na\nc\n'
+#+end_src
+** sort
+#+begin_src python
+import sys
+print("\n".join(sorted(sys.stdin.read().split())[:int(limit)]))
+#+end_src
+** show
+#+begin_src bash :var raw=fetch
+echo "first: $(head -n1)"
+echo "raw: $raw" | tr '\n' ' '
+#+end_src
+"###;
+    let doc = parse_org_doc(&mut doc_str.as_bytes(), "doc".to_string(), "bash").unwrap();
+    let report = doc.lookup_nodes(DOC_NODE_ROOT_ID, &["report"])[0];
+    let settings = RunSettings {
+        org_file: Path::new("jobs.org"),
+        dir_base: Path::new("."),
+        pipeline: true,
+        ..test_settings()
+    };
+    let tasks = build_tasks(&doc, &[report], Some(&[]), &settings);
+    let names: Vec<&str> = tasks.iter().map(|t| t.name.as_str()).collect();
+    assert_eq!(names, vec!["fetch", "sort", "show"]);
+    let langs: Vec<&str> = tasks.iter().map(|t| t.interpreter.as_str()).collect();
+    assert_eq!(langs, vec!["bash", "python", "bash"]);
+    assert_eq!(tasks[0].stdin, None);
+    assert_eq!(tasks[2].stdin, Some(TaskInput::Output(1)));
+    assert_eq!(tasks[2].deps, vec![1]);
+    assert_eq!(tasks[2].inputs, vec![("raw".to_string(), 0)]);
+    assert!(tasks[2].env.iter().all(|(k, _)| k != "raw"));
+
+    let dir = tempfile::tempdir().unwrap();
+    let log = RunLog::create(dir.path(), "run1", "doc.report", Path::new("jobs.org")).unwrap();
+    let options = ScheduleOptions {
+        prefix_output: true,
+        log: Some(log),
+        ..test_schedule_options()
+    };
+    let status = run_tasks(&tasks, &options);
+    assert!(status.iter().all(|s| s.is_success()), "{:?}", status);
+    let log = options.log.unwrap();
+    assert_eq!(log.read_output(1, &tasks[1], 1).0, "a\nb\n");
+    assert_eq!(log.read_output(2, &tasks[2], 1).0, "first: a\nraw: b a c ");
+}
+
+#[test]
+fn stdin_sources() {
+    let doc_str = r###"
+#+NAME: hosts
+#+begin_example
+web1
+,* web2
+#+end_example
+* inventory
+#+begin_src bash
+echo db1
+#+end_src
+* count
+#+begin_src bash :stdin hosts
+wc -l
+#+end_src
+* merge
+#+begin_src bash :stdin job:inventory
+cat
+#+end_src
+* load
+#+begin_src bash :input data/input.csv
+cat
+#+end_src
+* report
+#+begin_src bash :input job:inventory
+cat
+#+end_src
+"###;
+    let doc = parse_org_doc(&mut doc_str.as_bytes(), "doc".to_string(), "bash").unwrap();
+    assert_eq!(doc.example("hosts"), Some("web1\n* web2\n"));
+    let settings = RunSettings {
+        org_file: Path::new("jobs.org"),
+        ..test_settings()
+    };
+    let build = |job: &str, settings: &RunSettings| {
+        let node = doc.lookup_nodes(DOC_NODE_ROOT_ID, &[job])[0];
+        let order = doc.get_dependency_order(node, ".").unwrap();
+        return build_tasks(&doc, &order, Some(&[]), settings);
+    };
+    let here = RunSettings {
+        dir_base: Path::new("."),
+        ..test_settings()
+    };
+    let count = build("count", &here);
+    assert_eq!(
+        count[0].stdin,
+        Some(TaskInput::Example {
+            name: "hosts".to_string(),
+            text: "web1\n* web2\n".to_string()
+        })
+    );
+    let merge = build("merge", &settings);
+    assert_eq!(merge.len(), 2);
+    assert_eq!(merge[1].stdin, Some(TaskInput::Output(0)));
+    assert_eq!(merge[1].deps, vec![0]);
+    let report = build("report", &settings);
+    assert_eq!(report.len(), 2);
+    assert_eq!(report[1].stdin, Some(TaskInput::Output(0)));
+    assert_eq!(report[1].deps, vec![0]);
+    let load = build("load", &settings);
+    assert_eq!(
+        load[0].stdin,
+        Some(TaskInput::File(PathBuf::from("/work/data/input.csv")))
+    );
+    let from_cli = RunSettings {
+        stdin_from: Some("input.csv"),
+        ..settings
+    };
+    let load = build("load", &from_cli);
+    assert_eq!(
+        load[0].stdin,
+        Some(TaskInput::File(PathBuf::from("input.csv")))
+    );
+
+    let dir = tempfile::tempdir().unwrap();
+    let log = RunLog::create(dir.path(), "run1", "doc.count", Path::new("jobs.org")).unwrap();
+    let options = ScheduleOptions {
+        log: Some(log),
+        ..test_schedule_options()
+    };
+    let status = run_tasks(&count, &options);
+    assert_eq!(status, vec![TaskStatus::Exited(0)]);
+    let output = options.log.unwrap().read_output(0, &count[0], 1).0;
+    assert_eq!(output.trim(), "2");
+}
+
+#[test]
+fn parse_size1() {
+    assert_eq!(parse_size("4096"), Some(4096));
+    assert_eq!(parse_size("100k"), Some(100 * 1024));
+    assert_eq!(parse_size("512M"), Some(512 << 20));
+    assert_eq!(parse_size("2GiB"), Some(2 << 30));
+    assert_eq!(parse_size("1.5KB"), Some(1536));
+    assert_eq!(parse_size("10 bananas"), None);
+    assert_eq!(parse_size(""), None);
+}
+
+#[test]
+fn resource_limits() {
+    let doc_str = r###"
+* batch
+:PROPERTIES:
+:MAX_MEMORY: 1G
+:NICE: 5
+:END:
+** crunch
+:PROPERTIES:
+:MAX_CPU_SECONDS: 1m
+:MAX_FILE_SIZE: 10M
+:END:
+#+begin_src bash
+crunch
+#+end_src
+"###;
+    let doc = parse_org_doc(&mut doc_str.as_bytes(), "doc".to_string(), "bash").unwrap();
+    let crunch = doc.lookup_nodes(DOC_NODE_ROOT_ID, &["crunch"])[0];
+    assert_eq!(
+        job_limits(&doc, crunch),
+        Limits {
+            memory: Some(1 << 30),
+            cpu_seconds: Some(60),
+            file_size: Some(10 << 20),
+            nice: Some(5),
+        }
+    );
+
+    let mut write = bash_task("write", "head -c 4096 /dev/zero > big", vec![]);
+    write.limits.file_size = Some(1024);
+    let mut spin = bash_task("spin", "while :; do :; done", vec![]);
+    spin.limits.cpu_seconds = Some(1);
+    let current = unsafe { libc::nice(0) };
+    let mut nice = bash_task("nice", &format!("test $(nice) = {}", current + 3), vec![]);
+    nice.limits.nice = Some(3);
+    let dir = tempfile::tempdir().unwrap();
+    for t in [&mut write, &mut spin, &mut nice] {
+        t.dir = Some(dir.path().to_path_buf());
+    }
+    let options = ScheduleOptions {
+        jobs: 3,
+        fail_fast: false,
+        prefix_output: true,
+        ..test_schedule_options()
+    };
+    let status = run_tasks(&[write, spin, nice], &options);
+    assert_eq!(
+        status,
+        vec![
+            TaskStatus::LimitExceeded(ExceededLimit::FileSize),
+            TaskStatus::LimitExceeded(ExceededLimit::CpuTime),
+            TaskStatus::Exited(0)
+        ]
+    );
+    assert_eq!(aggregate_status(&status), 128 + libc::SIGXFSZ);
+}
+
+#[test]
+fn job_locks() {
+    let doc_str = r###"
+* migrate
+:PROPERTIES:
+:LOCK: t
+:END:
+** users
+* db
+:PROPERTIES:
+:LOCK: database
+:END:
+** seed
+* report
+"###;
+    let doc = parse_org_doc(&mut doc_str.as_bytes(), "doc".to_string(), "bash").unwrap();
+    let node = |name: &str| doc.lookup_nodes(DOC_NODE_ROOT_ID, &[name])[0];
+    assert_eq!(
+        job_lock(&doc, node("users"), "doc.migrate.users", false),
+        Some("doc.migrate.users".to_string())
+    );
+    assert_eq!(
+        job_lock(&doc, node("seed"), "doc.db.seed", false),
+        Some("database".to_string())
+    );
+    assert_eq!(job_lock(&doc, node("report"), "doc.report", false), None);
+    assert_eq!(
+        job_lock(&doc, node("report"), "doc.report", true),
+        Some("doc.report".to_string())
+    );
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = lock_path(dir.path(), Path::new("/work/jobs.org"), "doc.migrate");
+    let first = match JobLock::acquire(&path, false) {
+        Ok(lock) => lock,
+        Err(_) => panic!("the lock is free"),
+    };
+    assert_eq!(first.stale, None);
+    let holder = read_lock(&path).unwrap();
+    assert_eq!(holder.pid, std::process::id() as i32);
+    match JobLock::acquire(&path, false) {
+        Err(LockError::Held(Some(h))) => assert_eq!(h, holder),
+        _ => panic!("the lock is held"),
+    }
+    first.release().unwrap();
+    assert_eq!(read_lock(&path), None);
+
+    //a holder that died without releasing the lock
+    let mut child = std::process::Command::new("true").spawn().unwrap();
+    let dead = child.id();
+    child.wait().unwrap();
+    let stale = format!(
+        "pid: {}\nuser: alice\nstarted: 2026-10-18T09:30:00.000Z\n",
+        dead
+    );
+    std::fs::write(&path, stale).unwrap();
+    match JobLock::acquire(&path, false) {
+        Ok(lock) => assert_eq!(lock.stale.map(|h| h.user), Some("alice".to_string())),
+        Err(_) => panic!("a stale lock is taken over"),
+    }
+}
+
+#[test]
+fn mask_split_secrets() {
+    let mut m = Masker::new(&["hunter2".to_string(), "hun".to_string(), String::new()]);
+    let mut out = m.feed(b"pass: hun");
+    out.extend(m.feed(b"ter"));
+    out.extend(m.feed(b"2, short: hun"));
+    out.extend(m.feed(b"gry h"));
+    out.extend(m.finish(b"un"));
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "pass: ****, short: ****gry ****"
+    );
+    let mut none = Masker::new(&[]);
+    assert_eq!(none.feed(b"hunter2"), b"hunter2".to_vec());
+}
+
+#[test]
+fn masked_output() {
+    let doc_str = r###"
+* deploy
+:PROPERTIES:
+:ARGS: token
+:SECRET: token
+:SECRETS: API_KEY
+:END:
+#+begin_src bash
+printf '%s' "$token" | cut -c1-3
+printf 'key=%s\n' "$API_KEY" >&2
+printf 'tok'; sleep 0.1; printf 'en=%s\n' "$token"
+#+end_src
+"###;
+    let doc = parse_org_doc(&mut doc_str.as_bytes(), "doc".to_string(), "bash").unwrap();
+    let deploy = doc.lookup_nodes(DOC_NODE_ROOT_ID, &["deploy"])[0];
+    let overrides = [("API_KEY".to_string(), "k-123".to_string())];
+    let settings = RunSettings {
+        org_file: Path::new("jobs.org"),
+        env: &overrides,
+        ..test_settings()
+    };
+    let args = vec!["--token=s3cret".to_string()];
+    let mut tasks = build_tasks(&doc, &[deploy], Some(&args), &settings);
+    assert_eq!(tasks[0].mask, vec!["s3cret", "k-123"]);
+    assert!(tasks[0].secrets.contains(&"API_KEY".to_string()));
+    tasks[0].dir = None;
+
+    let dir = tempfile::tempdir().unwrap();
+    let log = RunLog::create(dir.path(), "run1", "doc.deploy", Path::new("jobs.org")).unwrap();
+    let options = ScheduleOptions {
+        log: Some(log),
+        ..test_schedule_options()
+    };
+    let status = run_tasks(&tasks, &options);
+    assert!(status.iter().all(|s| s.is_success()), "{:?}", status);
+    let log = options.log.unwrap();
+    let (stdout, stderr) = log.read_output(0, &tasks[0], 1);
+    assert_eq!(stdout, "s3c\ntoken=****\n");
+    assert!(stderr.contains("key=****\n"), "{}", stderr);
+    assert!(!stderr.contains("k-123"));
+}
+
+#[test]
+fn keep_script_trace() {
+    use std::os::unix::fs::PermissionsExt;
+    let dir = tempfile::tempdir().unwrap();
+    let script = dir.path().join("scripts/01-hello.sh");
+    let (out, err) = (dir.path().join("stdout"), dir.path().join("stderr"));
+    let options = RunOptions {
+        log: Some((out.clone(), err.clone())),
+        script: Some(script.clone()),
+        trace: true,
+        ..RunOptions::default()
+    };
+    let no_args: Vec<&str> = vec![];
+    let status = spawn_code("bash", "echo hi\n", &no_args, &options)
+        .unwrap()
+        .wait()
+        .unwrap();
+    assert!(status.success());
+    assert_eq!(std::fs::read_to_string(&script).unwrap(), "echo hi\n");
+    let mode = std::fs::metadata(&script).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o755);
+    assert_eq!(std::fs::read_to_string(&out).unwrap(), "hi\n");
+    assert_eq!(std::fs::read_to_string(&err).unwrap(), "+ echo hi\n");
+
+    assert_eq!(trace_args("sh"), Some(&["-x"][..]));
+    assert_eq!(trace_args("perl"), None);
+    let options = RunOptions {
+        trace: true,
+        ..RunOptions::default()
+    };
+    assert!(spawn_code("perl", "1;\n", &no_args, &options).is_err());
+}
+
+#[test]
+fn without_option1() {
+    let args: Vec<OsString> = ["-f", "jobs.org", "--watch", "run", "build", "--", "--watch"]
+        .iter()
+        .map(OsString::from)
+        .collect();
+    let expected: Vec<OsString> = ["-f", "jobs.org", "run", "build", "--", "--watch"]
+        .iter()
+        .map(OsString::from)
+        .collect();
+    assert_eq!(without_option(args, "--watch"), expected);
+}
+
+#[test]
+fn watch_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let org_file = dir.path().join("jobs.org");
+    let doc_str = r###"
+#+ENV_FILE: base.env
+* data
+:PROPERTIES:
+:SOURCES: raw.csv
+:DIR: data
+:END:
+#+begin_src bash
+make
+#+end_src
+* report
+:PROPERTIES:
+:DEPENDS: data
+:END:
+#+begin_src bash :sources report.tmpl
+render
+#+end_src
+"###;
+    std::fs::write(&org_file, doc_str).unwrap();
+    let files = watched_files(&org_file, "report", ".", dir.path());
+    let base = std::fs::canonicalize(dir.path()).unwrap();
+    assert_eq!(
+        files,
+        vec![
+            base.join("base.env"),
+            dir.path().join("data/raw.csv"),
+            org_file.clone(),
+            dir.path().join("report.tmpl"),
+        ]
+    );
+    assert_eq!(
+        watched_files(&org_file, "missing", ".", dir.path()),
+        vec![org_file]
+    );
+}
+
+#[test]
+fn parse_timestamp1() {
+    let ts = parse_timestamp("<2026-10-20 Tue 09:00 +1d>").unwrap();
+    assert_eq!((ts.year, ts.month, ts.day), (2026, 10, 20));
+    assert_eq!(ts.time, Some((9, 0)));
+    assert_eq!(
+        ts.repeater,
+        Some(Repeater {
+            kind: RepeatKind::Cumulate,
+            count: 1,
+            unit: RepeatUnit::Day
+        })
+    );
+    let ts = parse_timestamp("<2026-10-20 Tue 09:00-10:30 .+2w -1d>").unwrap();
+    assert_eq!(ts.time, Some((9, 0)));
+    assert_eq!(ts.repeater.unwrap().kind, RepeatKind::Restart);
+    assert_eq!(parse_timestamp("<2026-10-20 ++1m>").unwrap().time, None);
+    assert_eq!(parse_timestamp("<2026-02-30 Mon>"), None);
+    assert_eq!(parse_timestamp("<2026-10-20 Tue 9:5>"), None);
+    assert_eq!(parse_timestamp("<2026-10-20 Tue +1x>"), None);
+    assert_eq!(parse_timestamp("[2026-10-20 Tue]"), None);
+}
+
+#[test]
+fn timestamp_fire_times() {
+    let at = |s: &str| parse_timestamp(s).unwrap().start();
+    let daily = parse_timestamp("<2026-10-20 Tue 09:00 +1d>").unwrap();
+    let start = daily.start();
+    assert_eq!(daily.first_after(start - 1), Some(start));
+    assert_eq!(
+        daily
+            .first_after(at("<2026-11-03 Tue 12:00>"))
+            .map(format_local),
+        Some("2026-11-04 Wed 09:00".to_string())
+    );
+    let once = parse_timestamp("<2026-10-20 Tue 09:00>").unwrap();
+    assert_eq!(once.first_after(start), None);
+    assert_eq!(once.next_run(start, start + 60), None);
+
+    //a run three days late: + goes through every time it missed, ++ skips
+    //them and .+ counts from the end of the run
+    let late = at("<2026-10-23 Fri 10:00>");
+    assert_eq!(
+        daily.next_run(start, late).map(format_local),
+        Some("2026-10-21 Wed 09:00".to_string())
+    );
+    let catch_up = parse_timestamp("<2026-10-20 Tue 09:00 ++1d>").unwrap();
+    assert_eq!(
+        catch_up.next_run(start, late).map(format_local),
+        Some("2026-10-24 Sat 09:00".to_string())
+    );
+    let restart = parse_timestamp("<2026-10-20 Tue 09:00 .+1d>").unwrap();
+    assert_eq!(
+        restart.next_run(start, late).map(format_local),
+        Some("2026-10-24 Sat 10:00".to_string())
+    );
+    let monthly = parse_timestamp("<2020-01-15 Wed +1m>").unwrap();
+    assert_eq!(
+        monthly
+            .first_after(at("<2026-10-20 Tue>"))
+            .map(format_local),
+        Some("2026-11-15 Sun 00:00".to_string())
+    );
+}
+
+#[test]
+fn scheduled_logbook() {
+    let doc_str = r###"* backup
+SCHEDULED: <2026-10-20 Tue 09:00 +1d>
+:PROPERTIES:
+:DIR: /tmp
+:END:
+#+begin_src bash
+backup
+#+end_src
+** verify
+DEADLINE: <2026-10-21 Wed> SCHEDULED: <2026-10-20 Tue 10:00>
+:LOGBOOK:
+- Ran [2026-10-19 Mon 10:00]--[2026-10-19 Mon 10:01]: ok
+:END:
+* other
+"###;
+    let doc = parse_org_doc(&mut doc_str.as_bytes(), "doc".to_string(), "bash").unwrap();
+    let jobs: Vec<(String, String)> = scheduled_jobs(&doc)
+        .into_iter()
+        .map(|(name, ts)| (name.join("."), format_local(ts.start())))
+        .collect();
+    assert_eq!(
+        jobs,
+        vec![
+            ("doc.backup".to_string(), "2026-10-20 Tue 09:00".to_string()),
+            (
+                "doc.backup.verify".to_string(),
+                "2026-10-20 Tue 10:00".to_string()
+            ),
+        ]
+    );
+    let other = doc.lookup_nodes(DOC_NODE_ROOT_ID, &["other"])[0];
+    assert_eq!(doc.get_node(other).linum(), 14);
+    //what orgjob cannot schedule is left to org, and the daemon reports it
+    let habit = parse_org_doc(
+        &mut "* x\nSCHEDULED: <2026-10-20 Tue .+1d/3d>\n* y\nSCHEDULED: <2026-13-01>\n".as_bytes(),
+        "doc".to_string(),
+        "bash",
+    )
+    .unwrap();
+    assert!(scheduled_jobs(&habit).is_empty());
+    assert_eq!(
+        habit.unscheduled(),
+        &[
+            (2, "SCHEDULED: <2026-10-20 Tue .+1d/3d>".to_string()),
+            (4, "SCHEDULED: <2026-13-01>".to_string())
+        ]
+    );
+
+    let dir = tempfile::tempdir().unwrap();
+    let org_file = dir.path().join("jobs.org");
+    std::fs::write(&org_file, doc_str).unwrap();
+    let name = |path: &str| -> Vec<String> { path.split('.').map(|n| n.to_string()).collect() };
+    add_logbook_entry(&org_file, &name("doc.backup"), ".", "- first").unwrap();
+    add_logbook_entry(&org_file, &name("doc.backup.verify"), ".", "- second").unwrap();
+    let text = std::fs::read_to_string(&org_file).unwrap();
+    assert!(text.starts_with(
+        "* backup\nSCHEDULED: <2026-10-20 Tue 09:00 +1d>\n:PROPERTIES:\n:DIR: /tmp\n:END:\n:LOGBOOK:\n- first\n:END:\n#+begin_src bash\n"
+    ));
+    assert!(text.contains(
+        "SCHEDULED: <2026-10-20 Tue 10:00>\n:LOGBOOK:\n- second\n- Ran [2026-10-19 Mon 10:00]"
+    ));
+    assert!(text.ends_with("* other\n"));
+    assert!(add_logbook_entry(&org_file, &name("doc.gone"), ".", "- x").is_err());
+
+    let args: Vec<OsString> = vec!["-f".into(), "jobs.org".into(), "--".into(), "-v".into()];
+    let name = vec!["doc".to_string(), "backup".to_string()];
+    let expected: Vec<OsString> = vec![
+        "-f".into(),
+        "jobs.org".into(),
+        "run".into(),
+        "^=doc.=backup".into(),
+        "--".into(),
+        "-v".into(),
+    ];
+    assert_eq!(run_args(&args, &name, "."), expected);
+}
+
+#[test]
+fn query_modes() {
+    let doc_str = r###"
+* build
+* rebuild
+* build-docs
+* deploy
+** staging
+*** db-1
+** prod
+*** db-12
+*** db-x
+* tools
+** deploy
+*** prod
+** release [1/3]
+** what?
+** deploy v1.2
+** x a*b? y
+** progress [50%]
+"###;
+    let doc = parse_org_doc(&mut doc_str.as_bytes(), "doc".to_string(), "bash").unwrap();
+    let names = |q: &str| -> Vec<String> {
+        let query = parse_query(q, ".").unwrap();
+        return doc
+            .find(&query)
+            .iter()
+            .map(|n| doc.get_fullname(*n).join("."))
+            .collect();
+    };
+    assert_eq!(
+        names("build"),
+        vec!["doc.build", "doc.rebuild", "doc.build-docs"]
+    );
+    assert_eq!(names("=build"), vec!["doc.build"]);
+    assert_eq!(names("build*"), vec!["doc.build", "doc.build-docs"]);
+    assert_eq!(
+        names("^deploy.*.db-?"),
+        vec!["doc.deploy.staging.db-1", "doc.deploy.prod.db-x"]
+    );
+    assert_eq!(
+        names("deploy.prod"),
+        vec!["doc.deploy.prod", "doc.tools.deploy.prod"]
+    );
+    assert_eq!(names("^deploy.prod"), vec!["doc.deploy.prod"]);
+    assert_eq!(names("^=doc.=deploy.=prod"), vec!["doc.deploy.prod"]);
+    assert_eq!(names("^prod"), Vec::<String>::new());
+    assert_eq!(
+        names(r"/^db-\d+$/"),
+        vec!["doc.deploy.staging.db-1", "doc.deploy.prod.db-12"]
+    );
+    assert_eq!(names(r"prod./^db-\d{2}$/"), vec!["doc.deploy.prod.db-12"]);
+    assert_eq!(
+        names("^[!b]*"),
+        vec!["doc", "doc.rebuild", "doc.deploy", "doc.tools"]
+    );
+    //a cookie is not much of a glob, and a backslash makes a wildcard plain
+    assert_eq!(names("release [1/3]"), vec!["doc.tools.release [1/3]"]);
+    assert_eq!(names(r"what\?"), vec!["doc.tools.what?"]);
+    assert_eq!(names(r"build\*"), Vec::<String>::new());
+    assert_eq!(names("progress [50%]"), vec!["doc.tools.progress [50%]"]);
+    assert_eq!(names("re*[1/3]"), vec!["doc.tools.release [1/3]"]);
+    assert_eq!(names("a*b?"), Vec::<String>::new());
+    assert_eq!(
+        exact_query(&["doc".to_string(), "deploy".to_string()], "."),
+        "^=doc.=deploy"
+    );
+    let v12 = exact_query(
+        &[
+            "doc".to_string(),
+            "tools".to_string(),
+            "deploy v1.2".to_string(),
+        ],
+        ".",
+    );
+    assert_eq!(v12, r"^=doc.=tools.=deploy v1\.2");
+    assert_eq!(names(&v12), vec!["doc.tools.deploy v1.2"]);
+    let a_dot_b = parse_query("/a.b/.c", ".").unwrap();
+    assert_eq!(a_dot_b.segments.len(), 2);
+    assert_eq!(
+        parse_query("deploy..prod", ".").err(),
+        Some(QueryError::EmptySegment {
+            query: "deploy..prod".to_string()
+        })
+    );
+    assert_eq!(
+        parse_query("/db-", ".").err(),
+        Some(QueryError::RegexNotClosed {
+            query: "/db-".to_string()
+        })
+    );
+    assert!(parse_query("/(/", ".").is_err());
+}
+
+#[test]
+fn cli_job_args() {
+    let m = cli_app().get_matches_from(vec!["orgjob", "-f", "jobs.org", "run", "build", "-j", "4"]);
+    assert_eq!(m.value_of("job"), Some("build"));
+    assert_eq!(m.value_of("jobs"), Some("4"));
+    assert!(m.values_of("args").is_none());
+    let m = cli_app().get_matches_from(vec![
+        "orgjob", "-f", "jobs.org", "run", "build", "--", "--host", "web1",
+    ]);
+    let args: Vec<&str> = m.values_of("args").unwrap().collect();
+    assert_eq!(args, vec!["--host", "web1"]);
+    assert!(m.value_of("jobs").is_none());
+}
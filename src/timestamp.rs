@@ -0,0 +1,225 @@
+extern crate libc;
+
+///how a repeated timestamp moves on after a run
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum RepeatKind {
+    ///`+1d`: to the next time it comes, which may still be in the past
+    Cumulate,
+    ///`++1d`: to the first time it comes in the future
+    CatchUp,
+    ///`.+1d`: one interval after the end of the run
+    Restart,
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum RepeatUnit {
+    Hour,
+    Day,
+    Week,
+    Month,
+    Year,
+}
+
+impl RepeatUnit {
+    ///about how long it is, to skip most of a long way without stepping
+    fn approx_seconds(self) -> i64 {
+        return match self {
+            RepeatUnit::Hour => 3600,
+            RepeatUnit::Day => 86_400,
+            RepeatUnit::Week => 604_800,
+            RepeatUnit::Month => 2_629_746,
+            RepeatUnit::Year => 31_556_952,
+        };
+    }
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct Repeater {
+    pub kind: RepeatKind,
+    pub count: u32,
+    pub unit: RepeatUnit,
+}
+
+///an active org timestamp like `<2026-10-20 Tue 09:00 +1d>`, in local time
+#[derive(PartialEq, Clone, Debug)]
+pub struct Timestamp {
+    pub year: i32,
+    pub month: u32,
+    pub day: u32,
+    ///hour and minute, midnight when none
+    pub time: Option<(u32, u32)>,
+    pub repeater: Option<Repeater>,
+}
+
+fn parse_repeater(s: &str) -> Option<Repeater> {
+    let (kind, rest) = if let Some(rest) = s.strip_prefix("++") {
+        (RepeatKind::CatchUp, rest)
+    } else if let Some(rest) = s.strip_prefix(".+") {
+        (RepeatKind::Restart, rest)
+    } else {
+        (RepeatKind::Cumulate, s.strip_prefix('+')?)
+    };
+    let unit = match rest.chars().last()? {
+        'h' => RepeatUnit::Hour,
+        'd' => RepeatUnit::Day,
+        'w' => RepeatUnit::Week,
+        'm' => RepeatUnit::Month,
+        'y' => RepeatUnit::Year,
+        _ => return None,
+    };
+    let count: u32 = rest[..rest.len() - 1].parse().ok()?;
+    if count == 0 {
+        return None;
+    }
+    return Some(Repeater { kind, count, unit });
+}
+
+fn parse_time(s: &str) -> Option<(u32, u32)> {
+    //of a range like 09:00-10:30 only the start counts
+    let start = s.split('-').next()?;
+    let (h, m) = start.split_once(':')?;
+    if m.len() != 2 {
+        return None;
+    }
+    let (h, m): (u32, u32) = (h.parse().ok()?, m.parse().ok()?);
+    if h > 23 || m > 59 {
+        return None;
+    }
+    return Some((h, m));
+}
+
+fn days_in_month(year: i32, month: u32) -> u32 {
+    return match month {
+        4 | 6 | 9 | 11 => 30,
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        _ => 31,
+    };
+}
+
+///parse an active timestamp, with its angle brackets. The day name is not
+///checked and warning periods like `-2d` are skipped.
+pub fn parse_timestamp(s: &str) -> Option<Timestamp> {
+    let inner = s.trim().strip_prefix('<')?.strip_suffix('>')?;
+    let mut words = inner.split_whitespace();
+    let mut date = words.next()?.split('-');
+    let year: i32 = date.next()?.parse().ok()?;
+    let month: u32 = date.next()?.parse().ok()?;
+    let day: u32 = date.next()?.parse().ok()?;
+    if date.next().is_some() || !(1..=12).contains(&month) {
+        return None;
+    }
+    if day < 1 || day > days_in_month(year, month) {
+        return None;
+    }
+    let mut ts = Timestamp {
+        year,
+        month,
+        day,
+        time: None,
+        repeater: None,
+    };
+    for w in words {
+        if w.chars().all(|c| c.is_alphabetic()) {
+            continue;
+        } else if w.starts_with(|c: char| c.is_ascii_digit()) {
+            ts.time = Some(parse_time(w)?);
+        } else if w.starts_with('-') {
+            continue;
+        } else {
+            ts.repeater = Some(parse_repeater(w)?);
+        }
+    }
+    return Some(ts);
+}
+
+fn to_tm(t: i64) -> libc::tm {
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    let t = t as libc::time_t;
+    unsafe {
+        libc::localtime_r(&t, &mut tm);
+    }
+    return tm;
+}
+
+///mktime takes fields out of range, so that the 32nd of a month is the
+///first of the next one and 25 hours are a day and an hour
+fn from_tm(mut tm: libc::tm) -> i64 {
+    tm.tm_isdst = -1;
+    return unsafe { libc::mktime(&mut tm) } as i64;
+}
+
+///the local time t plus n times the unit, keeping the time of day across
+///changes to and from daylight saving time
+fn add_units(t: i64, unit: RepeatUnit, n: i64) -> i64 {
+    let mut tm = to_tm(t);
+    let n = n as libc::c_int;
+    match unit {
+        RepeatUnit::Hour => tm.tm_hour += n,
+        RepeatUnit::Day => tm.tm_mday += n,
+        RepeatUnit::Week => tm.tm_mday += 7 * n,
+        RepeatUnit::Month => tm.tm_mon += n,
+        RepeatUnit::Year => tm.tm_year += n,
+    }
+    return from_tm(tm);
+}
+
+impl Timestamp {
+    ///seconds since the epoch of the timestamp itself
+    pub fn start(&self) -> i64 {
+        let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+        let (hour, min) = self.time.unwrap_or((0, 0));
+        tm.tm_year = self.year - 1900;
+        tm.tm_mon = self.month as libc::c_int - 1;
+        tm.tm_mday = self.day as libc::c_int;
+        tm.tm_hour = hour as libc::c_int;
+        tm.tm_min = min as libc::c_int;
+        return from_tm(tm);
+    }
+
+    ///the k-th time it comes, the timestamp itself being the 0th
+    fn occurrence(&self, r: &Repeater, k: i64) -> i64 {
+        return add_units(self.start(), r.unit, k * r.count as i64);
+    }
+
+    ///the first time it comes after t, none when it does not any more
+    pub fn first_after(&self, t: i64) -> Option<i64> {
+        let start = self.start();
+        if start > t {
+            return Some(start);
+        }
+        let r = self.repeater?;
+        let step = r.unit.approx_seconds() * r.count as i64;
+        let mut k = ((t - start) / step - 2).max(0);
+        while self.occurrence(&r, k) <= t {
+            k += 1;
+        }
+        return Some(self.occurrence(&r, k));
+    }
+
+    ///when to run again after a run for the time fired that ended at
+    ///finished, none without a repeater
+    pub fn next_run(&self, fired: i64, finished: i64) -> Option<i64> {
+        let r = self.repeater?;
+        return match r.kind {
+            RepeatKind::Cumulate => self.first_after(fired),
+            RepeatKind::CatchUp => self.first_after(finished.max(fired)),
+            RepeatKind::Restart => Some(add_units(finished, r.unit, r.count as i64)),
+        };
+    }
+}
+
+///local time like 2026-10-20 Tue 09:00, as in org timestamps
+pub fn format_local(t: i64) -> String {
+    const DAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+    let tm = to_tm(t);
+    return format!(
+        "{:04}-{:02}-{:02} {} {:02}:{:02}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        DAYS[tm.tm_wday as usize % 7],
+        tm.tm_hour,
+        tm.tm_min
+    );
+}
@@ -0,0 +1,102 @@
+use super::runner::*;
+use super::scheduler::*;
+use super::stamp::*;
+use std::ffi::OsString;
+use std::io::{stdout, Result, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus};
+use std::thread::sleep;
+use std::time::{Duration, Instant, SystemTime};
+
+const POLL: Duration = Duration::from_millis(200);
+///how long the files have to stay the same after a change before a new run
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+fn snapshot(files: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    return files.iter().map(|f| mtime(f)).collect();
+}
+
+///wait for a change to one of the files and for the edits to settle; the
+///signal when orgjob got SIGINT or SIGTERM instead
+fn wait_for_change(files: &[PathBuf], child: &mut Option<Child>) -> Result<Option<i32>> {
+    let seen = snapshot(files);
+    loop {
+        sleep(POLL);
+        if let Some(sig) = take_signal() {
+            return Ok(Some(sig));
+        }
+        if let Some(c) = child.as_mut() {
+            if let Some(status) = c.try_wait()? {
+                println!(
+                    "\n{}, waiting for changes to {} files",
+                    describe(status),
+                    files.len()
+                );
+                *child = None;
+            }
+        }
+        let mut changed = snapshot(files);
+        if changed == seen {
+            continue;
+        }
+        loop {
+            sleep(DEBOUNCE);
+            let now = snapshot(files);
+            if now == changed {
+                return Ok(None);
+            }
+            changed = now;
+        }
+    }
+}
+
+fn describe(status: ExitStatus) -> String {
+    return match exit_status(status) {
+        TaskStatus::Exited(0) => "done".to_string(),
+        s => s.to_string(),
+    };
+}
+
+///stop a run with SIGTERM, which orgjob passes on to its tasks, and with
+///SIGKILL when it is still there after the grace period
+fn stop(mut child: Child) -> Result<()> {
+    unsafe {
+        libc::kill(child.id() as libc::pid_t, libc::SIGTERM);
+    }
+    let start = Instant::now();
+    while child.try_wait()?.is_none() {
+        if start.elapsed() >= KILL_GRACE {
+            child.kill()?;
+            child.wait()?;
+            break;
+        }
+        sleep(Duration::from_millis(10));
+    }
+    return Ok(());
+}
+
+///run program with args, and again every time one of the files it depends
+///on changes, clearing the screen before. A run still going when the files
+///change is stopped first. The files are asked for again before every run,
+///since a change can add or remove some.
+///SIGINT or SIGTERM stop the run and the watch, the exit code then tells which.
+pub fn watch_command(
+    program: &Path,
+    args: &[OsString],
+    files: impl Fn() -> Vec<PathBuf>,
+) -> Result<i32> {
+    catch_signals();
+    loop {
+        let watched = files();
+        print!("\x1b[2J\x1b[H");
+        stdout().flush()?;
+        let mut child = Some(Command::new(program).args(args).spawn()?);
+        let sig = wait_for_change(&watched, &mut child)?;
+        if let Some(child) = child {
+            stop(child)?;
+        }
+        if let Some(sig) = sig {
+            return Ok(128 + sig);
+        }
+    }
+}